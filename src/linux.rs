@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    env, fs, io, mem,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::Path,
+    time::Duration,
+};
+
+const DISKSTATS_SECTOR_SIZE: u64 = 512;
+
+/// Cumulative bytes read and written per block device, keyed by kernel device name (`sda1`,
+/// `dm-0`, ...), as reported by `/proc/diskstats`.
+pub fn disk_io_counters() -> Option<HashMap<String, (u64, u64)>> {
+    let content = fs::read_to_string("/proc/diskstats").ok()?;
+    let counters = content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let name = fields.get(2)?;
+            let sectors_read: u64 = fields.get(5)?.parse().ok()?;
+            let sectors_written: u64 = fields.get(9)?.parse().ok()?;
+            Some((
+                name.to_string(),
+                (
+                    sectors_read * DISKSTATS_SECTOR_SIZE,
+                    sectors_written * DISKSTATS_SECTOR_SIZE,
+                ),
+            ))
+        })
+        .collect();
+    Some(counters)
+}
+
+/// Resolves a disk name as reported by sysinfo (`/dev/sda1`, `/dev/mapper/root`, ...) to the
+/// kernel device name used in `/proc/diskstats`, following symlinks.
+pub fn block_device_name(disk_name: &Path) -> Option<String> {
+    let device = fs::canonicalize(disk_name).ok()?;
+    if !device.starts_with("/dev") {
+        return None;
+    }
+    Some(device.file_name()?.to_string_lossy().to_string())
+}
+
+const UTMP_RECORD_SIZE: usize = 384;
+const UTMP_USER_PROCESS: i16 = 7;
+
+/// User names of the login sessions recorded in utmp, one per session. `None` when there is no
+/// utmp, as in most containers.
+pub fn login_sessions() -> Option<Vec<String>> {
+    let content = fs::read("/run/utmp")
+        .or_else(|_| fs::read("/var/run/utmp"))
+        .ok()?;
+    let users = content
+        .chunks_exact(UTMP_RECORD_SIZE)
+        .filter(|record| i16::from_ne_bytes([record[0], record[1]]) == UTMP_USER_PROCESS)
+        .map(|record| {
+            // ut_user, a NUL-padded char[32] after ut_type, ut_pid, ut_line and ut_id.
+            let user = &record[44..76];
+            let len = user
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(user.len());
+            String::from_utf8_lossy(&user[..len]).to_string()
+        })
+        .collect();
+    Some(users)
+}
+
+/// Link speed of a network interface in Mbit/s. `None` for interfaces that report -1 or can't
+/// report one at all, like virtual ones, and for interfaces that are down.
+pub fn link_speed_mbps(interface: &str) -> Option<u32> {
+    let speed: i64 = fs::read_to_string(Path::new("/sys/class/net").join(interface).join("speed"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    u32::try_from(speed).ok().filter(|&speed| speed > 0)
+}
+
+/// Memory breakdown from `/proc/meminfo` that sysinfo doesn't expose, in bytes.
+pub struct MemDetail {
+    pub cached: u64,
+    pub buffers: u64,
+    pub shared: u64,
+}
+
+pub fn mem_detail() -> Option<MemDetail> {
+    let content = fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = content
+            .lines()
+            .find(|line| line.split(':').next() == Some(name))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kib * 1024)
+    };
+    Some(MemDetail {
+        cached: field("Cached")?,
+        buffers: field("Buffers")?,
+        shared: field("Shmem")?,
+    })
+}
+
+/// Minor and major page faults of a process since it started, from `/proc/<pid>/stat`.
+pub fn page_faults(pid: u32) -> Option<(u64, u64)> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is in parentheses and may contain spaces, so count fields after it.
+    let (_, rest) = content.rsplit_once(')')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // minflt and majflt are fields 10 and 12, and `rest` starts at field 3, the state.
+    Some((fields.get(7)?.parse().ok()?, fields.get(9)?.parse().ok()?))
+}
+
+/// Thermal throttle events since boot, summed over the `core_throttle_count` of every CPU in
+/// `/sys/devices/system/cpu`. `None` when no CPU has one, as on most non-Intel CPUs and in VMs.
+pub fn throttle_count() -> Option<u64> {
+    let mut total = None;
+    for entry in fs::read_dir("/sys/devices/system/cpu").ok()?.flatten() {
+        let name = entry.file_name();
+        let Some(index) = name.to_str().and_then(|name| name.strip_prefix("cpu")) else {
+            continue;
+        };
+        if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        let path = entry.path().join("thermal_throttle/core_throttle_count");
+        if let Some(count) = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| content.trim().parse::<u64>().ok())
+        {
+            *total.get_or_insert(0) += count;
+        }
+    }
+    total
+}
+
+pub fn entropy_avail() -> Option<u32> {
+    fs::read_to_string("/proc/sys/kernel/random/entropy_avail")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether `path` can be read, listing it if it's a directory.
+pub fn check_readable(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::read_dir(path).map(drop)
+    } else {
+        fs::File::open(path).map(drop)
+    }
+}
+
+/// Number of CPUs in the affinity mask of asher, from `sched_getaffinity(2)`.
+pub fn allowed_cpu_count() -> Option<usize> {
+    // SAFETY: an all-zero cpu_set_t is a valid empty set, and sched_getaffinity writes at most
+    // the size passed to it.
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return None;
+        }
+        usize::try_from(libc::CPU_COUNT(&set)).ok()
+    }
+}
+
+/// Sends a state update such as `READY=1` to the systemd notification socket. Does nothing when
+/// `$NOTIFY_SOCKET` isn't set, i.e. when asher isn't running as a notify-type service.
+pub fn sd_notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?),
+        None => socket.send_to(state.as_bytes(), &path),
+    }?;
+    Ok(())
+}
+
+/// How often systemd expects a watchdog ping, which is half of `$WATCHDOG_USEC` as recommended by
+/// `sd_watchdog_enabled(3)`. `None` when the watchdog isn't enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Aggregate CPU time counters from the `cpu` line of `/proc/stat`, in clock ticks. Columns
+/// that older kernels don't report are `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimeCounters {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: Option<u64>,
+    pub irq: Option<u64>,
+    pub softirq: Option<u64>,
+    pub steal: Option<u64>,
+}
+
+impl CpuTimeCounters {
+    /// Sum of all states. Guest time is already accounted for in `user` and `nice`.
+    pub fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait.unwrap_or(0)
+            + self.irq.unwrap_or(0)
+            + self.softirq.unwrap_or(0)
+            + self.steal.unwrap_or(0)
+    }
+}
+
+pub fn cpu_time_counters() -> Option<CpuTimeCounters> {
+    let content = fs::read_to_string("/proc/stat").ok()?;
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map_while(|field| field.parse().ok())
+        .collect();
+    Some(CpuTimeCounters {
+        user: *fields.first()?,
+        nice: *fields.get(1)?,
+        system: *fields.get(2)?,
+        idle: *fields.get(3)?,
+        iowait: fields.get(4).copied(),
+        irq: fields.get(5).copied(),
+        softirq: fields.get(6).copied(),
+        steal: fields.get(7).copied(),
+    })
+}
+
+/// Physical package (socket) of each logical CPU, keyed by processor number, from the
+/// `physical id` entries of `/proc/cpuinfo`. `None` when the kernel doesn't report them, as on
+/// many ARM systems.
+pub fn cpu_packages() -> Option<HashMap<usize, usize>> {
+    let content = fs::read_to_string("/proc/cpuinfo").ok()?;
+    let mut packages = HashMap::new();
+    let mut processor = None;
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "processor" => processor = value.trim().parse().ok(),
+            "physical id" => {
+                if let (Some(processor), Ok(package)) = (processor, value.trim().parse()) {
+                    packages.insert(processor, package);
+                }
+            }
+            _ => {}
+        }
+    }
+    (!packages.is_empty()).then_some(packages)
+}
@@ -1,13 +1,71 @@
+#[cfg(target_os = "linux")]
+mod linux;
+mod sink;
+mod table;
+mod template;
+#[cfg(unix)]
+mod unix;
+
 use std::{
-    sync::{Mutex, OnceLock},
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, OnceLock,
+    },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use serde::{Deserialize, Serialize};
-use sysinfo::{CpuExt, DiskExt, NetworkExt, NetworksExt, ProcessExt, System, SystemExt};
+use sysinfo::{
+    ComponentExt, CpuExt, CpuRefreshKind, DiskExt, NetworkExt, NetworksExt, Pid, PidExt, Process,
+    ProcessExt, ProcessRefreshKind, RefreshKind, System, SystemExt,
+};
 
 static SYSTEM: OnceLock<Mutex<System>> = OnceLock::new();
+/// Set by --quiet.
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set by --unit-system si.
+static SI_UNITS: AtomicBool = AtomicBool::new(false);
+/// Output lines written so far, for --max-lines.
+static LINES_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+/// The --pid-file written at startup, removed again on exit.
+static PID_FILE: OnceLock<std::path::PathBuf> = OnceLock::new();
+/// When the system was last refreshed, and how long before that the previous refresh was.
+static LAST_REFRESH: OnceLock<Mutex<(Instant, Duration)>> = OnceLock::new();
+/// The same as `LAST_REFRESH` for network counters alone, which --net-refresh-interval refreshes
+/// less often.
+static LAST_NET_REFRESH: OnceLock<Mutex<(Instant, Duration)>> = OnceLock::new();
+/// When CPU usage was last refreshed, which has to be at least
+/// `System::MINIMUM_CPU_UPDATE_INTERVAL` apart to mean anything.
+static LAST_CPU_REFRESH: OnceLock<Mutex<Instant>> = OnceLock::new();
+/// Recent usage of each CPU for --core-sparkline, oldest first.
+static CORE_HISTORY: OnceLock<Mutex<Vec<VecDeque<f32>>>> = OnceLock::new();
+/// Recent aggregate CPU usage readings for --cpu-window.
+static CPU_USAGE_WINDOW: OnceLock<Mutex<VecDeque<(Instant, f32)>>> = OnceLock::new();
+static DISK_IO: OnceLock<Mutex<HashMap<String, (u64, u64)>>> = OnceLock::new();
+#[cfg(target_os = "linux")]
+static CPU_PACKAGES: OnceLock<Option<HashMap<usize, usize>>> = OnceLock::new();
+#[cfg(target_os = "linux")]
+static CPU_TIMES: OnceLock<Mutex<Option<linux::CpuTimeCounters>>> = OnceLock::new();
+/// Thermal throttle events at the previous sample, for --cpu-throttle.
+#[cfg(target_os = "linux")]
+static THROTTLE_COUNT: OnceLock<Mutex<Option<u64>>> = OnceLock::new();
+/// Wanted subsystems whose source under `/proc` is unreadable, and why. Probed at the first
+/// sample only, since permissions on those files don't change while asher runs.
+#[cfg(target_os = "linux")]
+static UNREADABLE_SOURCES: OnceLock<Vec<(Subsystem, String)>> = OnceLock::new();
+
+/// Prints a diagnostic to stderr, unless --quiet is set.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!("warning: {}", format_args!($($arg)*));
+        }
+    };
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct MemStats {
@@ -15,9 +73,21 @@ struct MemStats {
     used: u64,
     free: u64,
     available: u64,
-    total_swap: u64,
-    used_swap: u64,
-    free_swap: u64,
+    /// The swap fields are left out with --no-swap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_swap: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    used_swap: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    free_swap: Option<u64>,
+    /// Page cache, buffers and shared memory from /proc/meminfo, with --mem-detail. Cache and
+    /// buffers count towards `used` but are mostly reclaimable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buffers: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,21 +95,101 @@ struct CpuCoreStats {
     usage: f32,
 }
 
+/// Share of CPU time, in percent, spent in each state since the previous sample.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CpuTimes {
+    user: f32,
+    nice: f32,
+    system: f32,
+    idle: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iowait: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    irq: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    softirq: Option<f32>,
+    /// Reported as `cpu.steal_percent` instead.
+    #[serde(skip)]
+    steal: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CpuPackageStats {
+    id: usize,
+    usage: f32,
+    cpu_count: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct CpuStats {
     usage: f32,
     cpus: Vec<CpuCoreStats>,
+    packages: Vec<CpuPackageStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    times: Option<CpuTimes>,
+    /// Share of time, in percent, the hypervisor ran other guests while this one was runnable,
+    /// where the kernel reports it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steal_percent: Option<f32>,
+    /// Usage of all running processes added up, on the same scale as `usage`, with
+    /// --cpu-process-sum. Usually lower than `usage`, as exited processes and kernel threads
+    /// aren't accounted for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process_sum: Option<f32>,
+    /// Recent usage of each CPU as a sparkline, oldest reading first, with --core-sparkline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpus_sparkline: Option<Vec<String>>,
+    /// How many CPUs asher may run on under its affinity mask, as set by taskset or a cgroup
+    /// cpuset. Usage is still relative to all of `cpus`. Linux only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_cpus: Option<usize>,
+    /// Thermal throttle events across all cores since the previous sample, or since startup for
+    /// the first one, and whether there were any, with --cpu-throttle. Left out where the kernel
+    /// doesn't count them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throttling: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throttle_events: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct DiskPartStats {
     name: String,
     mount_point: String,
+    fs_type: String,
     total: u64,
     free: u64,
     used: u64,
+    read: Option<u64>,
+    write: Option<u64>,
+    /// Inode counts, with --inodes on Unix. Left out for filesystems without a fixed inode
+    /// table, which report none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes_total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes_used: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes_free: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes_used_percent: Option<f32>,
+}
+
+impl DiskPartStats {
+    fn set_inodes(&mut self, total: u64, free: u64) {
+        if total == 0 {
+            return;
+        }
+        let used = total.saturating_sub(free);
+        self.inodes_total = Some(total);
+        self.inodes_used = Some(used);
+        self.inodes_free = Some(free);
+        self.inodes_used_percent = Some(used as f32 * 100.0 / total as f32);
+    }
 }
 
+/// `read`/`write` are the bytes processes read and wrote since the previous sample and
+/// `read_rate`/`write_rate` the same per second. `read_total`/`write_total` are cumulative, but
+/// since they are summed over the running processes they drop when a process exits.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct DiskStats {
     total: u64,
@@ -47,48 +197,850 @@ struct DiskStats {
     used: u64,
     read: u64,
     write: u64,
+    read_rate: u64,
+    write_rate: u64,
+    read_total: u64,
+    write_total: u64,
     disks: Vec<DiskPartStats>,
+    /// Copy of the entry in `disks` for the root filesystem, or the system drive on Windows.
+    /// Null when it isn't among them.
+    root_disk: Option<DiskPartStats>,
+    /// Mount point of the disk with the most bytes read and written since the previous sample.
+    /// Null when none of them had any I/O.
+    busiest_disk_io: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Reads a field that was written, even as null, as `Some`, so fields that are left out when
+/// disabled but null when unknown read back the way they were written.
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct NetInterfaceStats {
     name: String,
+    total_up: u64,
+    total_down: u64,
     up: u64,
     down: u64,
+    /// Bits per second since the previous sample, with --net-unit bits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    up_rate_bits: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    down_rate_bits: Option<u64>,
+    /// Negotiated link speed in Mbit/s, with --net-speed. Null when the interface doesn't report
+    /// one, as virtual ones don't.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    speed_mbps: Option<Option<u32>>,
+    /// How much of the link speed the busier direction used since the previous sample, with
+    /// --net-speed. Null along with `speed_mbps`.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    utilization_percent: Option<Option<f32>>,
+    /// For --dedup-interfaces-by-mac. Empty when there isn't one.
+    #[serde(skip)]
+    mac: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+struct NetInterfaceInfo {
+    name: String,
+    mac: String,
+    total_up: u64,
+    total_down: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct NetStats {
     total_up: u64,
     total_down: u64,
     up: u64,
     down: u64,
+    /// Bits per second since the previous sample, with --net-unit bits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    up_rate_bits: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    down_rate_bits: Option<u64>,
     interfaces: Vec<NetInterfaceStats>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProcStats {
+    count: usize,
+    /// Threads across all processes, where the platform exposes them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_threads: Option<usize>,
+}
+
+/// The few numbers --minimal writes instead of a whole sample.
+#[derive(Debug, Clone, Serialize)]
+struct MinimalStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mem: Option<MinimalMem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu: Option<MinimalCpu>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disks: Option<MinimalDisks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    net: Option<MinimalNet>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MinimalMem {
+    used_percent: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MinimalCpu {
+    usage: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MinimalDisks {
+    used_percent: f32,
+}
+
+/// Bytes per second since the previous sample.
+#[derive(Debug, Clone, Serialize)]
+struct MinimalNet {
+    up_rate: u64,
+    down_rate: u64,
+}
+
+impl From<&SysStats> for MinimalStats {
+    fn from(value: &SysStats) -> Self {
+        let percent = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                (part as f64 * 100.0 / total as f64) as f32
+            }
+        };
+        let seconds = net_refresh_interval().as_secs_f64();
+        let rate = |bytes: u64| {
+            if seconds > 0.0 {
+                (bytes as f64 / seconds) as u64
+            } else {
+                0
+            }
+        };
+        Self {
+            mem: value.mem.as_ref().map(|mem| MinimalMem {
+                used_percent: percent(mem.used, mem.total),
+            }),
+            cpu: value
+                .cpu
+                .as_ref()
+                .map(|cpu| MinimalCpu { usage: cpu.usage }),
+            disks: value.disks.as_ref().map(|disks| MinimalDisks {
+                used_percent: percent(disks.used, disks.total),
+            }),
+            net: value.net.as_ref().map(|net| MinimalNet {
+                up_rate: rate(net.up),
+                down_rate: rate(net.down),
+            }),
+        }
+    }
+}
+
+/// Usage of the filesystem containing `path`, for the disk-usage subcommand.
+#[derive(Debug, Clone, Serialize)]
+struct DiskUsage {
+    path: String,
+    mount_point: String,
+    fs_type: String,
+    total: u64,
+    used: u64,
+    free: u64,
+    used_percent: f32,
+    /// Unit the sizes are in.
+    #[serde(skip)]
+    unit: MemoryUnit,
+}
+
+/// How one metric compared against the baseline, for the check subcommand.
+#[derive(Debug, Clone, Serialize)]
+struct CheckResult {
+    metric: &'static str,
+    passed: bool,
+    /// Usage now and in the baseline, in percent.
+    usage: f32,
+    baseline: f32,
+    /// Percentage points usage grew by, and may grow by at most.
+    growth: f32,
+    max_growth: f32,
+}
+
+/// Whether a sample read back unchanged from one JSON form, for the selftest subcommand.
+#[derive(Debug, Clone, Serialize)]
+struct SelftestResult {
+    form: &'static str,
+    passed: bool,
+    /// What differed after reading the sample back, empty if it passed.
+    problems: Vec<String>,
+}
+
+/// Resource usage of the asher process itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SelfStats {
+    pid: u32,
+    cpu_usage: f32,
+    memory: u64,
+}
+
+/// Host-wide kernel state that belongs to no subsystem.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HostStats {
+    /// Bits of entropy in the kernel's random pool, with --entropy. Reads from /dev/random can
+    /// block when it runs low on older kernels.
+    entropy_avail: u32,
+}
+
+/// Login sessions, where the platform records them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionStats {
+    /// Distinct logged-in users.
+    users: usize,
+    sessions: usize,
+    /// Sorted names of the logged-in users, with --session-users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usernames: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TopProcess {
+    pid: u32,
+    name: String,
+    cpu_usage: f32,
+    memory: u64,
+    /// Page faults since the process started, with --faults. Major faults had to read from
+    /// disk, so a fast-growing count points at the process that is thrashing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minor_faults: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    major_faults: Option<u64>,
+}
+
+impl From<&Process> for TopProcess {
+    fn from(process: &Process) -> Self {
+        Self {
+            pid: process.pid().as_u32(),
+            name: process.name().to_string(),
+            cpu_usage: process.cpu_usage(),
+            memory: process.memory(),
+            minor_faults: None,
+            major_faults: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SensorStats {
+    label: String,
+    temperature: f32,
+    max: f32,
+    critical: Option<f32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SysStats {
-    mem: MemStats,
-    cpu: CpuStats,
-    disks: DiskStats,
-    net: NetStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mem: Option<MemStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu: Option<CpuStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disks: Option<DiskStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    net: Option<NetStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    processes: Option<ProcStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sessions: Option<SessionStats>,
+    /// With --include-self.
+    #[serde(rename = "self", skip_serializing_if = "Option::is_none")]
+    own: Option<SelfStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<HostStats>,
+    /// The --top-procs processes using the most CPU, busiest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_processes: Option<Vec<TopProcess>>,
+    /// Dotted paths of the metrics that jumped since the previous sample, with --spike-detect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spikes: Option<Vec<String>>,
+    /// Seconds since the previous sample was collected, with --sample-jitter-report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_interval: Option<f64>,
+    /// Milliseconds the refresh and building the sample took, with --emit-collect-duration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collect_duration_ms: Option<f64>,
+    /// Why a subsystem's section is missing, keyed `<subsystem>_error`, with --errors-inline.
+    #[serde(flatten)]
+    errors: BTreeMap<String, String>,
+    /// Set when collection exceeded --collect-timeout and this is a repeat of the last sample.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    /// Set on the extra samples --sigusr1 takes between scheduled ones.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    on_demand: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum Subsystem {
+    Mem,
+    Cpu,
+    Disks,
+    Net,
+    Processes,
+    Sessions,
+}
+
+impl Subsystem {
+    /// Whether `system` has no data for this subsystem although some is expected. Every
+    /// machine has memory, CPUs and a root filesystem; Unix systems always have at least a
+    /// loopback interface.
+    fn is_empty_in(self, system: &System) -> bool {
+        match self {
+            Subsystem::Mem => system.total_memory() == 0,
+            Subsystem::Cpu => system.cpus().is_empty(),
+            Subsystem::Disks => system.disks().is_empty(),
+            Subsystem::Net => cfg!(unix) && system.networks().iter().next().is_none(),
+            Subsystem::Processes => system.processes().is_empty(),
+            // Nobody being logged in is normal.
+            Subsystem::Sessions => false,
+        }
+    }
+
+    /// Why this subsystem's source under `/proc` is unreadable, as in hardened sandboxes.
+    #[cfg(target_os = "linux")]
+    fn source_error(self) -> Option<String> {
+        let source = match self {
+            Subsystem::Mem => "/proc/meminfo",
+            Subsystem::Cpu => "/proc/stat",
+            Subsystem::Disks => "/proc/mounts",
+            Subsystem::Net => "/proc/net/dev",
+            Subsystem::Processes => "/proc",
+            // Login records are optional; without them the section is simply left out.
+            Subsystem::Sessions => return None,
+        };
+        linux::check_readable(std::path::Path::new(source))
+            .err()
+            .map(|err| err.kind().to_string())
+    }
+
+    /// Why this subsystem couldn't be collected from `system`: its source being unreadable, or
+    /// it coming back empty.
+    fn collection_error(self, system: &System, args: &Args) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        {
+            let unreadable = UNREADABLE_SOURCES.get_or_init(|| {
+                Subsystem::value_variants()
+                    .iter()
+                    .filter(|subsystem| args.wants(**subsystem))
+                    .filter_map(|&subsystem| Some((subsystem, subsystem.source_error()?)))
+                    .collect()
+            });
+            if let Some((_, err)) = unreadable.iter().find(|(subsystem, _)| *subsystem == self) {
+                return Some(err.clone());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = args;
+        self.is_empty_in(system)
+            .then(|| "no data collected".to_string())
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Subsystem::Mem => "mem",
+            Subsystem::Cpu => "cpu",
+            Subsystem::Disks => "disks",
+            Subsystem::Net => "net",
+            Subsystem::Processes => "processes",
+            Subsystem::Sessions => "sessions",
+        }
+    }
+}
+
+impl SysStats {
+    /// Dotted path of the first float field that is NaN or infinite, which JSON can't represent.
+    fn non_finite_field(&self) -> Option<String> {
+        if let Some(own) = &self.own {
+            if !own.cpu_usage.is_finite() {
+                return Some("self.cpu_usage".to_string());
+            }
+        }
+        if let Some(top) = &self.top_processes {
+            if let Some(index) = top
+                .iter()
+                .position(|process| !process.cpu_usage.is_finite())
+            {
+                return Some(format!("top_processes.{}.cpu_usage", index));
+            }
+        }
+        if let Some(disks) = &self.disks {
+            if let Some(index) = disks.disks.iter().position(|disk| {
+                disk.inodes_used_percent
+                    .is_some_and(|percent| !percent.is_finite())
+            }) {
+                return Some(format!("disks.disks.{}.inodes_used_percent", index));
+            }
+        }
+        let cpu = self.cpu.as_ref()?;
+        let mut fields = vec![("cpu.usage".to_string(), cpu.usage)];
+        if let Some(process_sum) = cpu.process_sum {
+            fields.push(("cpu.process_sum".to_string(), process_sum));
+        }
+        if let Some(steal) = cpu.steal_percent {
+            fields.push(("cpu.steal_percent".to_string(), steal));
+        }
+        for (index, core) in cpu.cpus.iter().enumerate() {
+            fields.push((format!("cpu.cpus.{}.usage", index), core.usage));
+        }
+        for (index, package) in cpu.packages.iter().enumerate() {
+            fields.push((format!("cpu.packages.{}.usage", index), package.usage));
+        }
+        if let Some(times) = &cpu.times {
+            fields.extend([
+                ("cpu.times.user".to_string(), times.user),
+                ("cpu.times.nice".to_string(), times.nice),
+                ("cpu.times.system".to_string(), times.system),
+                ("cpu.times.idle".to_string(), times.idle),
+            ]);
+            for (name, value) in [
+                ("iowait", times.iowait),
+                ("irq", times.irq),
+                ("softirq", times.softirq),
+            ] {
+                if let Some(value) = value {
+                    fields.push((format!("cpu.times.{}", name), value));
+                }
+            }
+        }
+        fields
+            .into_iter()
+            .find(|(_, value)| !value.is_finite())
+            .map(|(field, _)| field)
+    }
+
+    fn retain(&mut self, only: &[Subsystem]) {
+        for &subsystem in Subsystem::value_variants() {
+            if !only.contains(&subsystem) {
+                self.remove(subsystem);
+            }
+        }
+    }
+
+    fn remove(&mut self, subsystem: Subsystem) {
+        match subsystem {
+            Subsystem::Mem => self.mem = None,
+            Subsystem::Cpu => self.cpu = None,
+            Subsystem::Disks => self.disks = None,
+            Subsystem::Net => self.net = None,
+            Subsystem::Processes => self.processes = None,
+            Subsystem::Sessions => self.sessions = None,
+        }
+    }
+
+    fn is_missing(&self, subsystem: Subsystem) -> bool {
+        match subsystem {
+            Subsystem::Mem => self.mem.as_ref().is_none_or(|mem| mem.total == 0),
+            Subsystem::Cpu => self.cpu.as_ref().is_none_or(|cpu| cpu.cpus.is_empty()),
+            Subsystem::Disks => self
+                .disks
+                .as_ref()
+                .is_none_or(|disks| disks.disks.is_empty()),
+            Subsystem::Net => self
+                .net
+                .as_ref()
+                .is_none_or(|net| net.interfaces.is_empty()),
+            Subsystem::Processes => self
+                .processes
+                .as_ref()
+                .is_none_or(|processes| processes.count == 0),
+            Subsystem::Sessions => self.sessions.is_none(),
+        }
+    }
+}
+
+impl MemStats {
+    /// Applies `f` to every size.
+    fn map_sizes(&mut self, f: impl Fn(u64) -> u64) {
+        for size in [
+            &mut self.total,
+            &mut self.used,
+            &mut self.free,
+            &mut self.available,
+        ] {
+            *size = f(*size);
+        }
+        for size in [
+            &mut self.total_swap,
+            &mut self.used_swap,
+            &mut self.free_swap,
+            &mut self.cached,
+            &mut self.buffers,
+            &mut self.shared,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *size = f(*size);
+        }
+    }
+}
+
+impl DiskStats {
+    /// Applies `f` to every size and byte count.
+    fn map_sizes(&mut self, f: impl Fn(u64) -> u64) {
+        for size in [
+            &mut self.total,
+            &mut self.free,
+            &mut self.used,
+            &mut self.read,
+            &mut self.write,
+            &mut self.read_rate,
+            &mut self.write_rate,
+            &mut self.read_total,
+            &mut self.write_total,
+        ] {
+            *size = f(*size);
+        }
+        for disk in self.disks.iter_mut().chain(&mut self.root_disk) {
+            disk.total = f(disk.total);
+            disk.free = f(disk.free);
+            disk.used = f(disk.used);
+            disk.read = disk.read.map(&f);
+            disk.write = disk.write.map(&f);
+        }
+    }
+
+    fn find_root_disk(&mut self) {
+        #[cfg(windows)]
+        let root = format!(
+            "{}\\",
+            std::env::var("SystemDrive").unwrap_or_else(|_| "C:".to_string())
+        );
+        #[cfg(not(windows))]
+        let root = "/";
+        self.root_disk = self
+            .disks
+            .iter()
+            .find(|disk| disk.mount_point == root)
+            .cloned();
+    }
+
+    fn find_busiest_disk_io(&mut self) {
+        self.busiest_disk_io = self
+            .disks
+            .iter()
+            .map(|disk| (disk.read.unwrap_or(0) + disk.write.unwrap_or(0), disk))
+            .filter(|&(io, _)| io > 0)
+            .max_by_key(|&(io, _)| io)
+            .map(|(_, disk)| disk.mount_point.clone());
+    }
+}
+
+impl DiskStats {
+    /// Drops disks matching `exclude` and recomputes the size totals from the remaining ones.
+    fn exclude_disks(&mut self, mut exclude: impl FnMut(&DiskPartStats) -> bool) {
+        self.disks.retain(|disk| !exclude(disk));
+        self.total = self.disks.iter().map(|disk| disk.total).sum();
+        self.free = self.disks.iter().map(|disk| disk.free).sum();
+        self.used = self.disks.iter().map(|disk| disk.used).sum();
+    }
+
+    /// Keeps the `max` disks with the most I/O, then the largest ones, and folds the rest into a
+    /// single `other` entry.
+    fn cap_disks(&mut self, max: usize) {
+        if self.disks.len() <= max {
+            return;
+        }
+        self.disks.sort_by_key(|disk| {
+            std::cmp::Reverse((disk.read.unwrap_or(0) + disk.write.unwrap_or(0), disk.total))
+        });
+        let rest = self.disks.split_off(max);
+        let sum_io =
+            |io: fn(&DiskPartStats) -> Option<u64>| rest.iter().filter_map(io).reduce(|a, b| a + b);
+        let mut other = DiskPartStats {
+            name: "other".to_string(),
+            mount_point: String::new(),
+            fs_type: String::new(),
+            total: rest.iter().map(|disk| disk.total).sum(),
+            free: rest.iter().map(|disk| disk.free).sum(),
+            used: rest.iter().map(|disk| disk.used).sum(),
+            read: sum_io(|disk| disk.read),
+            write: sum_io(|disk| disk.write),
+            inodes_total: None,
+            inodes_used: None,
+            inodes_free: None,
+            inodes_used_percent: None,
+        };
+        let inodes = |count: fn(&DiskPartStats) -> Option<u64>| rest.iter().filter_map(count).sum();
+        other.set_inodes(
+            inodes(|disk| disk.inodes_total),
+            inodes(|disk| disk.inodes_free),
+        );
+        self.disks.push(other);
+    }
+
+    /// Orders the disks by mount point, then device name, keeping the `other` entry of
+    /// `cap_disks` last.
+    fn sort_disks(&mut self) {
+        self.disks.sort_by(|a, b| {
+            let key = |disk: &DiskPartStats| {
+                (
+                    disk.name == "other" && disk.mount_point.is_empty(),
+                    disk.mount_point.clone(),
+                    disk.name.clone(),
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+    }
+}
+
+impl NetStats {
+    fn from_interfaces(interfaces: Vec<NetInterfaceStats>) -> Self {
+        Self {
+            total_up: interfaces.iter().map(|net| net.total_up).sum(),
+            total_down: interfaces.iter().map(|net| net.total_down).sum(),
+            up: interfaces.iter().map(|net| net.up).sum(),
+            down: interfaces.iter().map(|net| net.down).sum(),
+            up_rate_bits: None,
+            down_rate_bits: None,
+            interfaces,
+        }
+    }
+
+    /// Whether the aggregates are the sums over the listed interfaces, which every filter has to
+    /// preserve.
+    fn totals_match(&self) -> bool {
+        *self == Self::from_interfaces(self.interfaces.clone())
+    }
+
+    /// Keeps only the named interfaces, in the given order, and recomputes the aggregates from
+    /// them.
+    fn select_interfaces(&mut self, names: &[String], missing: MissingInterfaces) {
+        let mut interfaces = std::mem::take(&mut self.interfaces);
+        let selected = names
+            .iter()
+            .filter_map(
+                |name| match interfaces.iter().position(|net| &net.name == name) {
+                    Some(index) => Some(interfaces.swap_remove(index)),
+                    None => match missing {
+                        MissingInterfaces::Zero => Some(NetInterfaceStats {
+                            name: name.clone(),
+                            total_up: 0,
+                            total_down: 0,
+                            up: 0,
+                            down: 0,
+                            up_rate_bits: None,
+                            down_rate_bits: None,
+                            speed_mbps: None,
+                            utilization_percent: None,
+                            mac: String::new(),
+                        }),
+                        MissingInterfaces::Omit => None,
+                    },
+                },
+            )
+            .collect();
+        *self = Self::from_interfaces(selected);
+    }
+
+    /// Keeps one interface per MAC address, the one with the most traffic overall, and
+    /// recomputes the aggregates from them. A bond carries the traffic of its members and a VLAN
+    /// interface's traffic also passes through its parent, so the busiest one already accounts
+    /// for the others and adding them up would count the same traffic twice. Interfaces without
+    /// a MAC address, like loopback and tunnels, are all kept.
+    fn dedup_interfaces_by_mac(&mut self) {
+        let mut ranked: Vec<&NetInterfaceStats> = self.interfaces.iter().collect();
+        ranked.sort_by_key(|net| std::cmp::Reverse(net.total_up + net.total_down));
+        let mut busiest = HashMap::new();
+        for net in ranked {
+            busiest.entry(net.mac.clone()).or_insert(net.name.clone());
+        }
+        let mut interfaces = std::mem::take(&mut self.interfaces);
+        interfaces.retain(|net| net.mac.is_empty() || busiest[&net.mac] == net.name);
+        *self = Self::from_interfaces(interfaces);
+    }
+
+    /// Keeps the `max` interfaces with the most traffic this interval, then overall, and folds the
+    /// rest into a single `other` entry. The aggregates are unaffected.
+    fn cap_interfaces(&mut self, max: usize) {
+        if self.interfaces.len() <= max {
+            return;
+        }
+        self.interfaces.sort_by_key(|net| {
+            std::cmp::Reverse((net.up + net.down, net.total_up + net.total_down))
+        });
+        let rest = self.interfaces.split_off(max);
+        let other = NetInterfaceStats {
+            name: "other".to_string(),
+            total_up: rest.iter().map(|net| net.total_up).sum(),
+            total_down: rest.iter().map(|net| net.total_down).sum(),
+            up: rest.iter().map(|net| net.up).sum(),
+            down: rest.iter().map(|net| net.down).sum(),
+            up_rate_bits: None,
+            down_rate_bits: None,
+            speed_mbps: None,
+            utilization_percent: None,
+            mac: String::new(),
+        };
+        self.interfaces.push(other);
+    }
+
+    /// Applies `f` to every byte count, of the aggregates and of each interface.
+    fn map_bytes(&mut self, f: impl Fn(u64) -> u64) {
+        for bytes in [
+            &mut self.total_up,
+            &mut self.total_down,
+            &mut self.up,
+            &mut self.down,
+        ] {
+            *bytes = f(*bytes);
+        }
+        for interface in &mut self.interfaces {
+            interface.total_up = f(interface.total_up);
+            interface.total_down = f(interface.total_down);
+            interface.up = f(interface.up);
+            interface.down = f(interface.down);
+        }
+    }
+
+    /// Converts the byte counts of the last `interval` into the `*_rate_bits` fields.
+    fn add_bit_rates(&mut self, interval: Duration) {
+        let seconds = interval.as_secs_f64();
+        let rate = |bytes: u64| {
+            Some(if seconds > 0.0 {
+                (bytes as f64 * 8.0 / seconds) as u64
+            } else {
+                0
+            })
+        };
+        self.up_rate_bits = rate(self.up);
+        self.down_rate_bits = rate(self.down);
+        for interface in &mut self.interfaces {
+            interface.up_rate_bits = rate(interface.up);
+            interface.down_rate_bits = rate(interface.down);
+        }
+    }
+
+    /// Adds the link speed of each interface, and how much of it the traffic of the last
+    /// `interval` used in the busier direction.
+    fn add_link_speeds(&mut self, interval: Duration) {
+        let seconds = interval.as_secs_f64();
+        for interface in &mut self.interfaces {
+            let speed = link_speed_mbps(&interface.name);
+            interface.speed_mbps = Some(speed);
+            interface.utilization_percent = Some(speed.map(|speed| {
+                if seconds > 0.0 && speed > 0 {
+                    let bits = interface.up.max(interface.down) as f64 * 8.0 / seconds;
+                    (bits * 100.0 / (speed as f64 * 1e6)) as f32
+                } else {
+                    0.0
+                }
+            }));
+        }
+    }
+
+    /// Orders the interfaces by name, keeping the `other` entry of `cap_interfaces` last.
+    fn sort_interfaces(&mut self) {
+        self.interfaces
+            .sort_by(|a, b| (a.name == "other", &a.name).cmp(&(b.name == "other", &b.name)));
+    }
+}
+
+impl SysStats {
+    /// Memory and CPU only, without looking at disks, interfaces or processes. Used for
+    /// `--only mem,cpu --no-processes`, see [`Args::fast_path`].
+    fn mem_cpu(system: &System) -> Self {
+        let times = cpu_times();
+        Self {
+            mem: Some(MemStats {
+                total: system.total_memory(),
+                used: system.used_memory(),
+                free: system.free_memory(),
+                available: system.available_memory(),
+                total_swap: Some(system.total_swap()),
+                used_swap: Some(system.used_swap()),
+                free_swap: Some(system.free_swap()),
+                cached: None,
+                buffers: None,
+                shared: None,
+            }),
+            cpu: Some(CpuStats {
+                usage: if system.cpus().is_empty() {
+                    0.0
+                } else {
+                    system.cpus().iter().map(|cpu| cpu.cpu_usage()).sum::<f32>()
+                        / system.cpus().len() as f32
+                },
+                cpus: system
+                    .cpus()
+                    .iter()
+                    .map(|cpu| CpuCoreStats {
+                        usage: cpu.cpu_usage(),
+                    })
+                    .collect(),
+                packages: cpu_packages(system),
+                steal_percent: times.as_ref().and_then(|times| times.steal),
+                times,
+                process_sum: None,
+                cpus_sparkline: None,
+                allowed_cpus: allowed_cpus(),
+                throttling: None,
+                throttle_events: None,
+            }),
+            disks: None,
+            net: None,
+            processes: None,
+            sessions: None,
+            own: None,
+            host: None,
+            top_processes: None,
+            spikes: None,
+            sample_interval: None,
+            collect_duration_ms: None,
+            errors: BTreeMap::new(),
+            stale: false,
+            on_demand: false,
+        }
+    }
 }
 
 impl From<&System> for SysStats {
     fn from(value: &System) -> Self {
-        let total_cpu_usage =
-            value.cpus().iter().map(|cpu| cpu.cpu_usage()).sum::<f32>() / value.cpus().len() as f32;
+        let total_cpu_usage = if value.cpus().is_empty() {
+            0.0
+        } else {
+            value.cpus().iter().map(|cpu| cpu.cpu_usage()).sum::<f32>() / value.cpus().len() as f32
+        };
+        let times = cpu_times();
         Self {
-            mem: MemStats {
+            mem: Some(MemStats {
                 total: value.total_memory(),
                 used: value.used_memory(),
                 free: value.free_memory(),
                 available: value.available_memory(),
-                total_swap: value.total_swap(),
-                used_swap: value.used_swap(),
-                free_swap: value.free_swap(),
-            },
-            cpu: CpuStats {
+                total_swap: Some(value.total_swap()),
+                used_swap: Some(value.used_swap()),
+                free_swap: Some(value.free_swap()),
+                cached: None,
+                buffers: None,
+                shared: None,
+            }),
+            cpu: Some(CpuStats {
                 usage: total_cpu_usage,
                 cpus: value
                     .cpus()
@@ -97,23 +1049,60 @@ impl From<&System> for SysStats {
                         usage: cpu.cpu_usage(),
                     })
                     .collect(),
-            },
-            disks: {
+                packages: cpu_packages(value),
+                steal_percent: times.as_ref().and_then(|times| times.steal),
+                times,
+                // Process usage is relative to a single CPU.
+                process_sum: (!value.cpus().is_empty()).then(|| {
+                    value
+                        .processes()
+                        .values()
+                        .map(|process| process.cpu_usage())
+                        .sum::<f32>()
+                        / value.cpus().len() as f32
+                }),
+                cpus_sparkline: None,
+                allowed_cpus: allowed_cpus(),
+                throttling: None,
+                throttle_events: None,
+            }),
+            disks: Some({
                 let mut disks = DiskStats {
                     total: 0,
                     free: 0,
                     used: 0,
                     read: 0,
                     write: 0,
+                    read_rate: 0,
+                    write_rate: 0,
+                    read_total: 0,
+                    write_total: 0,
                     disks: Vec::new(),
+                    root_disk: None,
+                    busiest_disk_io: None,
                 };
+                let disk_io = disk_io_deltas();
                 for disk in value.disks() {
+                    #[cfg(target_os = "linux")]
+                    let io = linux::block_device_name(std::path::Path::new(disk.name()))
+                        .and_then(|device| disk_io.get(&device).copied());
+                    #[cfg(not(target_os = "linux"))]
+                    let io = disk_io
+                        .get(&disk.name().to_string_lossy().to_string())
+                        .copied();
                     let disk_part = DiskPartStats {
                         name: disk.name().to_string_lossy().to_string(),
                         mount_point: disk.mount_point().to_string_lossy().to_string(),
+                        fs_type: String::from_utf8_lossy(disk.file_system()).to_string(),
                         total: disk.total_space(),
                         free: disk.available_space(),
                         used: disk.total_space() - disk.available_space(),
+                        read: io.map(|(read, _)| read),
+                        write: io.map(|(_, write)| write),
+                        inodes_total: None,
+                        inodes_used: None,
+                        inodes_free: None,
+                        inodes_used_percent: None,
                     };
                     disks.total += disk_part.total;
                     disks.free += disk_part.free;
@@ -124,90 +1113,2954 @@ impl From<&System> for SysStats {
                 #[cfg(any(target_os = "windows", target_os = "freebsd"))]
                 {
                     if let Some((_, process)) = value.processes().iter().next() {
-                        disks.read += process.disk_usage().read_bytes;
-                        disks.write += process.disk_usage().written_bytes;
+                        let usage = process.disk_usage();
+                        disks.read += usage.read_bytes;
+                        disks.write += usage.written_bytes;
+                        disks.read_total += usage.total_read_bytes;
+                        disks.write_total += usage.total_written_bytes;
                     }
                 }
                 #[cfg(not(any(target_os = "windows", target_os = "freebsd")))]
                 {
                     value.processes().iter().for_each(|(_, process)| {
-                        disks.read += process.disk_usage().read_bytes;
-                        disks.write += process.disk_usage().written_bytes;
+                        let usage = process.disk_usage();
+                        disks.read += usage.read_bytes;
+                        disks.write += usage.written_bytes;
+                        disks.read_total += usage.total_read_bytes;
+                        disks.write_total += usage.total_written_bytes;
                     });
                 }
+                let seconds = refresh_interval().as_secs_f64();
+                if seconds > 0.0 {
+                    disks.read_rate = (disks.read as f64 / seconds) as u64;
+                    disks.write_rate = (disks.write as f64 / seconds) as u64;
+                }
                 disks
-            },
-            net: NetStats {
-                total_up: value
-                    .networks()
-                    .iter()
-                    .map(|(_, net)| net.total_transmitted())
-                    .sum(),
-                total_down: value
-                    .networks()
-                    .iter()
-                    .map(|(_, net)| net.total_received())
-                    .sum(),
-                up: value
-                    .networks()
-                    .iter()
-                    .map(|(_, net)| net.transmitted())
-                    .sum(),
-                down: value.networks().iter().map(|(_, net)| net.received()).sum(),
-                interfaces: value
+            }),
+            net: Some(NetStats::from_interfaces(
+                value
                     .networks()
                     .iter()
                     .map(|(name, net)| NetInterfaceStats {
                         name: name.clone(),
+                        total_up: net.total_transmitted(),
+                        total_down: net.total_received(),
                         up: net.transmitted(),
                         down: net.received(),
+                        up_rate_bits: None,
+                        down_rate_bits: None,
+                        speed_mbps: None,
+                        utilization_percent: None,
+                        mac: Some(net.mac_address())
+                            .filter(|mac| !mac.is_unspecified())
+                            .map_or(String::new(), |mac| mac.to_string()),
                     })
                     .collect(),
-            },
+            )),
+            processes: Some(ProcStats {
+                count: value.processes().len(),
+                #[cfg(target_os = "linux")]
+                total_threads: Some(
+                    value
+                        .processes()
+                        .values()
+                        .map(|process| process.tasks.len().max(1))
+                        .sum(),
+                ),
+                #[cfg(not(target_os = "linux"))]
+                total_threads: None,
+            }),
+            // Filled in by `sample` when wanted, as reading utmp isn't free.
+            sessions: None,
+            own: sysinfo::get_current_pid()
+                .ok()
+                .and_then(|pid| value.process(pid))
+                .map(|process| SelfStats {
+                    pid: process.pid().as_u32(),
+                    cpu_usage: process.cpu_usage(),
+                    memory: process.memory(),
+                }),
+            host: None,
+            top_processes: None,
+            spikes: None,
+            sample_interval: None,
+            collect_duration_ms: None,
+            errors: BTreeMap::new(),
+            stale: false,
+            on_demand: false,
         }
     }
 }
 
-#[derive(Debug, Clone, Parser)]
-enum SubCommand {
-    Loop {
-        #[clap(short, long, default_value = "1.0")]
-        interval: f32,
-    },
+/// Locks `mutex`, recovering it if a thread panicked while holding it. None of the shared state
+/// is left inconsistent by a panic halfway through an update, so it's safe to keep using.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warning!("recovering from a lock poisoned by a panicked thread");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
 }
 
-#[derive(Debug, Clone, Parser)]
-struct Args {
-    #[clap(subcommand)]
-    command: Option<SubCommand>,
+fn new_system(args: &Args) -> Mutex<System> {
+    let mut specifics = refresh_kind(args);
+    if specifics.disks() {
+        specifics = specifics.with_disks_list();
+    }
+    if specifics.networks() {
+        specifics = specifics.with_networks_list();
+    }
+    let system = System::new_with_specifics(specifics);
+    LAST_REFRESH.get_or_init(|| Mutex::new((Instant::now(), Duration::ZERO)));
+    LAST_NET_REFRESH.get_or_init(|| Mutex::new((Instant::now(), Duration::ZERO)));
+    LAST_CPU_REFRESH.get_or_init(|| Mutex::new(Instant::now()));
+    if !args.fast_path() {
+        disk_io_deltas();
+    }
+    cpu_times();
+    #[cfg(target_os = "linux")]
+    if args.cpu_throttle {
+        throttle_events();
+    }
+    Mutex::new(system)
 }
 
-fn main() {
-    let args = Args::parse();
-    SYSTEM.get_or_init(|| Mutex::new(System::new_all()));
+/// Builds a sample from the last refresh of `system`. Sections that couldn't be collected are
+/// left out, with the reason in `errors`.
+fn sample(system: &System, args: &Args) -> SysStats {
+    let mut stats = if args.fast_path() {
+        SysStats::mem_cpu(system)
+    } else {
+        SysStats::from(system)
+    };
+    if args.wants(Subsystem::Sessions) {
+        stats.sessions = session_stats();
+    }
+    if args.top_procs > 0 {
+        let mut top = top_processes(system, args.top_procs);
+        #[cfg(target_os = "linux")]
+        if args.faults {
+            for process in &mut top {
+                if let Some((minor, major)) = linux::page_faults(process.pid) {
+                    process.minor_faults = Some(minor);
+                    process.major_faults = Some(major);
+                }
+            }
+        }
+        stats.top_processes = Some(top);
+    }
+    #[cfg(target_os = "linux")]
+    if let (true, Some(mem)) = (args.mem_detail, &mut stats.mem) {
+        if let Some(detail) = linux::mem_detail() {
+            mem.cached = Some(detail.cached);
+            mem.buffers = Some(detail.buffers);
+            mem.shared = Some(detail.shared);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let (true, Some(cpu)) = (args.cpu_throttle, &mut stats.cpu) {
+        if let Some(events) = throttle_events() {
+            cpu.throttling = Some(events > 0);
+            cpu.throttle_events = Some(events);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if args.entropy {
+        stats.host = linux::entropy_avail().map(|entropy_avail| HostStats { entropy_avail });
+    }
+    for &subsystem in Subsystem::value_variants() {
+        if !args.wants(subsystem) {
+            continue;
+        }
+        if let Some(err) = subsystem.collection_error(system, args) {
+            stats.remove(subsystem);
+            stats
+                .errors
+                .insert(format!("{}_error", subsystem.name()), err);
+        }
+    }
+    stats
+}
 
-    match args.command {
-        Some(SubCommand::Loop { interval }) => loop_command(interval),
-        None => {
-            let mut system = SYSTEM
-                .get_or_init(|| Mutex::new(System::new_all()))
-                .lock()
-                .unwrap();
-            system.refresh_all();
-            let stats = SysStats::from(&*system);
+fn link_speed_mbps(interface: &str) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    return linux::link_speed_mbps(interface);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = interface;
+        None
+    }
+}
+
+/// Thermal throttle events since the previous call, or `None` on the first call and where the
+/// kernel doesn't count them.
+#[cfg(target_os = "linux")]
+fn throttle_events() -> Option<u64> {
+    let current = linux::throttle_count();
+    let previous = std::mem::replace(
+        &mut *lock(THROTTLE_COUNT.get_or_init(|| Mutex::new(None))),
+        current,
+    );
+    Some(current?.saturating_sub(previous?))
+}
+
+fn allowed_cpus() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    return linux::allowed_cpu_count();
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+fn session_stats() -> Option<SessionStats> {
+    #[cfg(target_os = "linux")]
+    {
+        let sessions = linux::login_sessions()?;
+        let mut usernames = sessions.clone();
+        usernames.sort();
+        usernames.dedup();
+        Some(SessionStats {
+            users: usernames.len(),
+            sessions: sessions.len(),
+            usernames: Some(usernames),
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// The `count` processes using the most CPU, then memory.
+fn top_processes(system: &System, count: usize) -> Vec<TopProcess> {
+    let mut processes: Vec<TopProcess> =
+        system.processes().values().map(TopProcess::from).collect();
+    processes.sort_by(|a, b| {
+        b.cpu_usage
+            .total_cmp(&a.cpu_usage)
+            .then(b.memory.cmp(&a.memory))
+    });
+    processes.truncate(count);
+    processes
+}
+
+/// The parts of `System` a sample needs, so nothing that isn't emitted gets refreshed:
+///
+/// - memory and swap for `mem`
+/// - CPU usage, but not frequency, for `cpu`
+/// - disk space for `disks`, plus per-process disk usage, which the I/O totals are summed from
+/// - network counters for `net`
+/// - the process list for `processes`, plus per-process CPU usage for --cpu-process-sum,
+///   --include-self and --top-procs
+///
+/// Process owners are never refreshed, nor are sensors and users, and `sessions` is read from
+/// utmp instead. --faults reads /proc itself for the few --top-procs processes, so it needs
+/// nothing more from sysinfo.
+fn refresh_kind(args: &Args) -> RefreshKind {
+    let mut kind = RefreshKind::new();
+    if args.wants(Subsystem::Mem) {
+        kind = kind.with_memory();
+    }
+    if args.wants(Subsystem::Cpu) {
+        kind = kind.with_cpu(CpuRefreshKind::new().with_cpu_usage());
+    }
+    if args.wants(Subsystem::Disks) {
+        kind = kind.with_disks();
+    }
+    if args.wants(Subsystem::Net) {
+        kind = kind.with_networks();
+    }
+    let mut processes = ProcessRefreshKind::new();
+    if args.wants(Subsystem::Disks) {
+        processes = processes.with_disk_usage();
+    }
+    if (args.cpu_process_sum && args.wants(Subsystem::Cpu))
+        || args.include_self
+        || args.top_procs > 0
+    {
+        processes = processes.with_cpu();
+    }
+    // --no-processes skips the process list even though the disk I/O totals need it.
+    if !args.no_processes
+        && (args.wants(Subsystem::Processes) || processes != ProcessRefreshKind::new())
+    {
+        kind = kind.with_processes(processes);
+    }
+    kind
+}
+
+/// Sleeps for --startup-warmup-ms before the first measured refresh, and on until
+/// `System::MINIMUM_CPU_UPDATE_INTERVAL` has passed since the initial one if the sample has CPU
+/// usage, since sysinfo keeps the old usage for refreshes closer together than that.
+fn startup_warmup(args: &Args) {
+    if args.startup_warmup_ms == 0 {
+        return;
+    }
+    thread::sleep(Duration::from_millis(args.startup_warmup_ms));
+    if args.wants(Subsystem::Cpu) {
+        let since_cpu_refresh = lock(LAST_CPU_REFRESH.get().unwrap()).elapsed();
+        thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL.saturating_sub(since_cpu_refresh));
+    }
+}
+
+/// Whether CPU usage can be refreshed `elapsed` after the last time, which sysinfo needs to be at
+/// least `System::MINIMUM_CPU_UPDATE_INTERVAL` to measure anything.
+fn cpu_refresh_due(elapsed: Duration) -> bool {
+    elapsed >= System::MINIMUM_CPU_UPDATE_INTERVAL
+}
 
-            println!("{}", serde_json::to_string(&stats).unwrap());
+/// Refreshes what the sample needs, then re-refreshes subsystems that came back empty even
+/// though this platform should always report something for them, up to `--refresh-retries`
+/// times.
+fn refresh(system: &mut System, args: &Args) {
+    let mut kind = refresh_kind(args);
+    let processes = kind.processes();
+    if kind.networks() {
+        let due = args
+            .net_refresh_interval
+            .is_none_or(|interval| lock(LAST_NET_REFRESH.get().unwrap()).0.elapsed() >= interval);
+        if due {
+            let now = Instant::now();
+            let mut last = lock(LAST_NET_REFRESH.get().unwrap());
+            *last = (now, now - last.0);
+        } else {
+            // sysinfo keeps the counters of the last network refresh, so the sample repeats them.
+            kind = kind.without_networks();
+        }
+    }
+    if kind.cpu().is_some() {
+        let mut last = lock(LAST_CPU_REFRESH.get().unwrap());
+        if cpu_refresh_due(last.elapsed()) {
+            *last = Instant::now();
+        } else {
+            // Usage over a shorter span is mostly noise, so repeat the last values instead. Only
+            // the loop samples again, so that's the only place the repeat shows.
+            static WARNED: AtomicBool = AtomicBool::new(false);
+            if matches!(args.command, Some(SubCommand::Loop(_)))
+                && !WARNED.swap(true, Ordering::Relaxed)
+            {
+                warning!(
+                    "sampling faster than CPU usage can be measured, repeating it until {}ms have \
+                     passed between refreshes",
+                    System::MINIMUM_CPU_UPDATE_INTERVAL.as_millis()
+                );
+            }
+            kind = kind.without_cpu();
+        }
+    }
+    system.refresh_specifics(kind);
+    mark_refreshed();
+    for attempt in 1..=args.refresh_retries {
+        let empty: Vec<Subsystem> = [
+            Subsystem::Mem,
+            Subsystem::Cpu,
+            Subsystem::Disks,
+            Subsystem::Net,
+            Subsystem::Processes,
+            Subsystem::Sessions,
+        ]
+        .into_iter()
+        .filter(|&subsystem| args.wants(subsystem) && subsystem.is_empty_in(system))
+        .collect();
+        if empty.is_empty() {
+            break;
+        }
+        warning!(
+            "{:?} came back empty, refreshing again ({}/{})",
+            empty,
+            attempt,
+            args.refresh_retries
+        );
+        for subsystem in empty {
+            match subsystem {
+                Subsystem::Mem => system.refresh_memory(),
+                Subsystem::Cpu => system.refresh_cpu(),
+                Subsystem::Disks => system.refresh_disks_list(),
+                Subsystem::Net => system.refresh_networks_list(),
+                Subsystem::Processes => {
+                    system.refresh_processes_specifics(processes.unwrap_or_default())
+                }
+                Subsystem::Sessions => {}
+            }
         }
     }
 }
 
-fn loop_command(interval: f32) {
-    loop {
-        let mut system = SYSTEM.get().unwrap().lock().unwrap();
-        thread::sleep(std::time::Duration::from_secs_f32(interval));
-        system.refresh_all();
-        let stats = SysStats::from(&*system);
+/// Takes one sample from the shared system, first re-enumerating disks and interfaces if
+/// `refresh_topology` is set.
+fn collect(args: &Args, refresh_topology: bool) -> SysStats {
+    let mut system = lock(SYSTEM.get().unwrap());
+    if refresh_topology {
+        system.refresh_disks_list();
+        system.refresh_networks_list();
+    }
+    refresh_and_sample(&mut system, args)
+}
+
+/// Refreshes `system` and builds a sample from it, timing both for --emit-collect-duration.
+fn refresh_and_sample(system: &mut System, args: &Args) -> SysStats {
+    let start = Instant::now();
+    refresh(system, args);
+    let mut stats = sample(system, args);
+    stats.collect_duration_ms = args
+        .emit_collect_duration
+        .then(|| start.elapsed().as_secs_f64() * 1000.0);
+    stats
+}
+
+fn mark_refreshed() {
+    let now = Instant::now();
+    let mut last = lock(LAST_REFRESH.get().unwrap());
+    *last = (now, now - last.0);
+}
+
+/// Time between the two most recent refreshes, which the per-refresh counters cover.
+fn refresh_interval() -> Duration {
+    LAST_REFRESH
+        .get()
+        .map_or(Duration::ZERO, |last| lock(last).1)
+}
+
+/// Time between the two most recent network refreshes, which the network counters cover.
+fn net_refresh_interval() -> Duration {
+    LAST_NET_REFRESH
+        .get()
+        .map_or(Duration::ZERO, |last| lock(last).1)
+}
+
+/// Mean usage of the logical CPUs of each physical package. Everything is reported as a single
+/// package when the topology isn't known.
+fn cpu_packages(system: &System) -> Vec<CpuPackageStats> {
+    #[cfg(target_os = "linux")]
+    let topology = CPU_PACKAGES.get_or_init(linux::cpu_packages).as_ref();
+    #[cfg(not(target_os = "linux"))]
+    let topology: Option<&HashMap<usize, usize>> = None;
+
+    let mut packages: Vec<CpuPackageStats> = Vec::new();
+    for (index, cpu) in system.cpus().iter().enumerate() {
+        let id = topology
+            .and_then(|topology| topology.get(&index))
+            .copied()
+            .unwrap_or(0);
+        match packages.iter_mut().find(|package| package.id == id) {
+            Some(package) => {
+                package.usage += cpu.cpu_usage();
+                package.cpu_count += 1;
+            }
+            None => packages.push(CpuPackageStats {
+                id,
+                usage: cpu.cpu_usage(),
+                cpu_count: 1,
+            }),
+        }
+    }
+    for package in &mut packages {
+        package.usage /= package.cpu_count as f32;
+    }
+    packages.sort_by_key(|package| package.id);
+    packages
+}
+
+/// CPU time breakdown since the previous call, or `None` on the first call and on platforms
+/// without `/proc/stat`.
+fn cpu_times() -> Option<CpuTimes> {
+    #[cfg(target_os = "linux")]
+    {
+        let current = linux::cpu_time_counters()?;
+        let previous = lock(CPU_TIMES.get_or_init(|| Mutex::new(None))).replace(current)?;
+        let elapsed = current.total().saturating_sub(previous.total());
+        if elapsed == 0 {
+            return None;
+        }
+        let percent =
+            |now: u64, before: u64| now.saturating_sub(before) as f32 * 100.0 / elapsed as f32;
+        let optional = |now: Option<u64>, before: Option<u64>| Some(percent(now?, before?));
+        Some(CpuTimes {
+            user: percent(current.user, previous.user),
+            nice: percent(current.nice, previous.nice),
+            system: percent(current.system, previous.system),
+            idle: percent(current.idle, previous.idle),
+            iowait: optional(current.iowait, previous.iowait),
+            irq: optional(current.irq, previous.irq),
+            softirq: optional(current.softirq, previous.softirq),
+            steal: optional(current.steal, previous.steal),
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+const SPARKLINE_LENGTH: usize = 10;
+
+/// Adds the current usage of each CPU to its history and renders the histories as sparklines.
+fn core_sparklines(cpus: &[CpuCoreStats]) -> Vec<String> {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let mut history = lock(CORE_HISTORY.get_or_init(|| Mutex::new(Vec::new())));
+    history.resize_with(cpus.len(), VecDeque::new);
+    history
+        .iter_mut()
+        .zip(cpus)
+        .map(|(readings, cpu)| {
+            if readings.len() == SPARKLINE_LENGTH {
+                readings.pop_front();
+            }
+            readings.push_back(cpu.usage);
+            readings
+                .iter()
+                .map(|usage| {
+                    let level = (usage.clamp(0.0, 100.0) / 100.0 * (BARS.len() - 1) as f32).round();
+                    BARS[level as usize]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Records `usage` and returns the mean of the readings taken within the last `window`.
+fn windowed_cpu_usage(usage: f32, window: Duration) -> f32 {
+    let mut readings = lock(CPU_USAGE_WINDOW.get_or_init(|| Mutex::new(VecDeque::new())));
+    let now = Instant::now();
+    readings.push_back((now, usage));
+    while readings
+        .front()
+        .is_some_and(|&(taken, _)| now - taken > window)
+    {
+        readings.pop_front();
+    }
+    readings.iter().map(|&(_, usage)| usage).sum::<f32>() / readings.len() as f32
+}
+
+/// Bytes read and written per block device since the previous call. Devices seen for the first
+/// time report zero; platforms without per-device counters report nothing.
+fn disk_io_deltas() -> HashMap<String, (u64, u64)> {
+    #[cfg(target_os = "linux")]
+    let counters = linux::disk_io_counters().unwrap_or_default();
+    #[cfg(not(target_os = "linux"))]
+    let counters: HashMap<String, (u64, u64)> = HashMap::new();
+
+    let mut previous = lock(DISK_IO.get_or_init(|| Mutex::new(HashMap::new())));
+    let deltas = counters
+        .iter()
+        .map(|(device, &(read, write))| {
+            let (prev_read, prev_write) = previous.get(device).copied().unwrap_or((read, write));
+            (
+                device.clone(),
+                (
+                    read.saturating_sub(prev_read),
+                    write.saturating_sub(prev_write),
+                ),
+            )
+        })
+        .collect();
+    *previous = counters;
+    deltas
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct LoopArgs {
+    #[clap(short, long, default_value = "1.0")]
+    interval: f32,
+    /// Send READY=1 to systemd on startup and ping its watchdog between samples (Linux only).
+    #[clap(long)]
+    systemd_notify: bool,
+    /// Skip samples that don't differ from the last emitted one. The first sample is always
+    /// emitted.
+    #[clap(long)]
+    emit_on_change: bool,
+    /// Largest difference between two float fields that still counts as unchanged for
+    /// --emit-on-change.
+    #[clap(long, default_value = "0.0")]
+    change_epsilon: f64,
+    /// Run one hidden collection, after the --startup-warmup-ms warm-up, before the first
+    /// interval so the first emitted sample already has meaningful CPU usage and rates.
+    #[clap(long)]
+    prime: bool,
+    /// Don't emit the first sample, which only serves as the baseline of the rates and CPU usage
+    /// of the next. --count counts emitted samples, so it's unaffected.
+    #[clap(long)]
+    drop_first_sample: bool,
+    /// Collect this many samples, one per interval, without emitting them, so rates and
+    /// --cpu-window averages have settled by the first emitted sample. Delays that sample by
+    /// about this many intervals on top of the usual one.
+    #[clap(long, default_value = "0")]
+    warmup_samples: usize,
+    /// Annotate each sample with the metrics that changed by more than --spike-threshold since
+    /// the previous sample.
+    #[clap(long)]
+    spike_detect: bool,
+    /// Relative change, in percent, that counts as a spike. A metric moving away from zero
+    /// always counts.
+    #[clap(long, default_value = "50.0")]
+    spike_threshold: f64,
+    /// Seconds between re-enumerations of the disk and network interface lists, so devices
+    /// that appear while looping get picked up. 0 disables it.
+    #[clap(long, default_value = "60.0")]
+    topology_refresh: f32,
+    /// Seconds to wait for a collection before giving up on it for this interval. The previous
+    /// sample is then emitted again, marked `"stale": true`, and the same stale values keep being
+    /// reused until the hung collection finishes, so the output cadence stays steady.
+    #[clap(long)]
+    collect_timeout: Option<f32>,
+    /// File to read the interval from, in seconds, whenever its modification time changes. Lets
+    /// another process adjust the sampling rate without a restart. Until the file can be read,
+    /// and whenever its contents are invalid, the current interval is kept.
+    #[clap(long)]
+    interval_file: Option<std::path::PathBuf>,
+    /// Take an extra sample, marked `"on_demand": true`, whenever asher receives SIGUSR1, without
+    /// moving the scheduled ones. A partial --batch is written out right after it. Unix only.
+    #[clap(long)]
+    sigusr1: bool,
+    /// Stop after emitting this many samples.
+    #[clap(long)]
+    count: Option<usize>,
+    /// Write the PID of asher to this file on startup and remove it on exit, including on
+    /// SIGINT and SIGTERM on Unix. Refuses to start while the file names a running process.
+    #[clap(long)]
+    pid_file: Option<std::path::PathBuf>,
+    /// Collect this many samples before writing them out together as one JSON array, to cut
+    /// down on writes. A partial batch is still written when --count is reached, when a sample
+    /// fails --strict-json or, on Unix, when asher is interrupted.
+    #[clap(long, default_value = "1")]
+    batch: usize,
+    /// Take samples on wall-clock multiples of --interval counted from --epoch-base, rather than
+    /// --interval after the previous one, so that separate runs agree on sample boundaries.
+    #[clap(long)]
+    align: bool,
+    /// Unix time, in seconds, that --align counts intervals from.
+    #[clap(long, default_value = "0.0", requires = "align")]
+    epoch_base: f64,
+}
+
+#[derive(Debug, Clone, Parser)]
+enum SubCommand {
+    Loop(LoopArgs),
+    /// Resolve and print the effective settings as JSON without collecting anything.
+    Validate,
+    /// List every temperature sensor with its current, maximum and critical temperature.
+    ListSensors,
+    /// List every network interface with its MAC address and cumulative counters.
+    ListNetworks,
+    /// Report the usage of the filesystem that contains the given path.
+    DiskUsage {
+        path: std::path::PathBuf,
+    },
+    /// Read samples written as JSON lines by an earlier run and write them again in the
+    /// current --format.
+    Replay {
+        /// File to read, or `-` for standard input.
+        #[clap(long)]
+        input: std::path::PathBuf,
+    },
+    /// Collect a sample and check that it reads back unchanged from each JSON form asher writes,
+    /// exiting with 2 if not. Worth running after an upgrade, before trusting recorded samples.
+    Selftest,
+    /// Collect repeatedly without sleeping and report how long each refresh step takes.
+    Bench {
+        #[clap(long, default_value = "1000")]
+        iterations: usize,
+    },
+    /// Collect a sample and compare CPU and memory usage against an earlier one, exiting with 2
+    /// if either grew by more than allowed.
+    Check {
+        /// Sample to compare against, as written by an earlier run. The first line that is a
+        /// sample is used.
+        #[clap(long)]
+        compare_to: std::path::PathBuf,
+        /// Percentage points that CPU usage may exceed the baseline's by.
+        #[clap(long)]
+        cpu_delta: Option<f32>,
+        /// Percentage points that the share of memory in use may exceed the baseline's by.
+        #[clap(long)]
+        mem_delta: Option<f32>,
+    },
+    /// Wait until a process with the given name or PID runs, exiting with 2 if none does before
+    /// --timeout.
+    WaitFor {
+        /// Name of the process, matched exactly.
+        #[clap(long, required_unless_present = "pid", conflicts_with = "pid")]
+        name: Option<String>,
+        #[clap(long)]
+        pid: Option<u32>,
+        /// Seconds to wait before giving up. Waits indefinitely if not given.
+        #[clap(long)]
+        timeout: Option<f32>,
+        /// Seconds between checks of the process list.
+        #[clap(long, default_value = "0.5")]
+        poll_interval: f32,
+        /// Print the process that was found, with its memory and the CPU usage since the check
+        /// before.
+        #[clap(long)]
+        print_stats: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum DataFd {
+    Stdout,
+    Stderr,
+}
+
+impl DataFd {
+    fn is_terminal(self) -> bool {
+        match self {
+            DataFd::Stdout => std::io::stdout().is_terminal(),
+            DataFd::Stderr => std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum MemoryUnit {
+    Bytes,
+    Kib,
+    Mib,
+    Gib,
+}
+
+impl MemoryUnit {
+    /// Bytes per unit, in powers of 1000 instead of 1024 with --unit-system si.
+    fn divisor(self) -> u64 {
+        let base: u64 = UnitSystem::current().base();
+        match self {
+            MemoryUnit::Bytes => 1,
+            MemoryUnit::Kib => base,
+            MemoryUnit::Mib => base.pow(2),
+            MemoryUnit::Gib => base.pow(3),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match (self, UnitSystem::current()) {
+            (MemoryUnit::Bytes, _) => "bytes",
+            (MemoryUnit::Kib, UnitSystem::Binary) => "kib",
+            (MemoryUnit::Mib, UnitSystem::Binary) => "mib",
+            (MemoryUnit::Gib, UnitSystem::Binary) => "gib",
+            (MemoryUnit::Kib, UnitSystem::Si) => "kb",
+            (MemoryUnit::Mib, UnitSystem::Si) => "mb",
+            (MemoryUnit::Gib, UnitSystem::Si) => "gb",
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match (self, UnitSystem::current()) {
+            (MemoryUnit::Bytes, _) => "B",
+            (MemoryUnit::Kib, UnitSystem::Binary) => "KiB",
+            (MemoryUnit::Mib, UnitSystem::Binary) => "MiB",
+            (MemoryUnit::Gib, UnitSystem::Binary) => "GiB",
+            (MemoryUnit::Kib, UnitSystem::Si) => "kB",
+            (MemoryUnit::Mib, UnitSystem::Si) => "MB",
+            (MemoryUnit::Gib, UnitSystem::Si) => "GB",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum UnitSystem {
+    /// Powers of 1024: KiB, MiB, GiB.
+    Binary,
+    /// Powers of 1000: kB, MB, GB.
+    Si,
+}
+
+impl UnitSystem {
+    /// The unit system set by --unit-system.
+    fn current() -> Self {
+        if SI_UNITS.load(Ordering::Relaxed) {
+            UnitSystem::Si
+        } else {
+            UnitSystem::Binary
+        }
+    }
+
+    fn base(self) -> u64 {
+        match self {
+            UnitSystem::Binary => 1024,
+            UnitSystem::Si => 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum NetUnit {
+    Bytes,
+    /// Also report the traffic as bits per second.
+    Bits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum Format {
+    /// Table when writing to a terminal, JSON otherwise.
+    Auto,
+    Json,
+    Table,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum ColorChoice {
+    /// Color when writing to a terminal and `$NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && is_terminal
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl Format {
+    fn resolve(self, data_fd: DataFd) -> Format {
+        match self {
+            Format::Auto => {
+                if data_fd.is_terminal() {
+                    Format::Table
+                } else {
+                    Format::Json
+                }
+            }
+            format => format,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum MissingInterfaces {
+    Zero,
+    Omit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum Case {
+    Snake,
+    Camel,
+}
+
+#[derive(Debug, Clone, Parser, Serialize)]
+struct Args {
+    #[clap(subcommand)]
+    #[serde(skip)]
+    command: Option<SubCommand>,
+    /// Milliseconds to wait between the initial and the measured refresh of a one-shot run.
+    /// Defaults to sysinfo's minimum CPU update interval, which shorter waits are stretched to
+    /// when CPU usage is collected, as sysinfo doesn't measure it over less; 0 disables the
+    /// warm-up, in which case CPU usage of the sample may read as zero.
+    #[clap(long, default_value_t = System::MINIMUM_CPU_UPDATE_INTERVAL.as_millis() as u64)]
+    startup_warmup_ms: u64,
+    /// Stream to write the collected data to.
+    #[clap(long, global = true, value_enum, default_value = "stdout")]
+    data_fd: DataFd,
+    /// Only collect and emit the given comma-separated subsystems.
+    #[clap(long, global = true, value_enum, value_delimiter = ',')]
+    only: Vec<Subsystem>,
+    /// Exit with an error if a subsystem requested through --only produced no data.
+    #[clap(long, global = true)]
+    fail_on_missing_subsystem: bool,
+    /// Unit memory and disk sizes are reported in, rounded down to whole units.
+    #[clap(long, global = true, value_enum, default_value = "bytes")]
+    memory_unit: MemoryUnit,
+    /// Whether --memory-unit and the sizes in tables count in powers of 1024 (KiB, MiB, GiB) or
+    /// of 1000 (kB, MB, GB). With si, `--memory-unit mib` reports MB.
+    #[clap(long, global = true, value_enum, default_value = "binary")]
+    unit_system: UnitSystem,
+    /// Report memory and swap in KiB, as sysinfo did before switching to bytes. Meant as a
+    /// migration aid for consumers that still expect the old unit; disk sizes are unaffected.
+    #[clap(long, global = true, conflicts_with = "memory_unit")]
+    legacy_kib_memory: bool,
+    /// Fail instead of emitting a sample with NaN or infinite numbers, which would otherwise
+    /// be serialized as null.
+    #[clap(long, global = true)]
+    strict_json: bool,
+    /// Only report the given comma-separated network interfaces, computing the totals from
+    /// them alone.
+    #[clap(long, global = true, value_delimiter = ',')]
+    interfaces: Vec<String>,
+    /// How to report interfaces named in --interfaces that don't exist at collection time.
+    #[clap(long, global = true, value_enum, default_value = "zero")]
+    missing_interfaces: MissingInterfaces,
+    /// How many times to re-refresh a subsystem that unexpectedly came back empty.
+    #[clap(long, global = true, default_value = "1")]
+    refresh_retries: u32,
+    /// Comma-separated filesystem types to leave out of the disk list and totals.
+    #[clap(
+        long,
+        global = true,
+        value_delimiter = ',',
+        default_value = "tmpfs,devtmpfs,ramfs,overlay,squashfs,proc,sysfs,devpts,cgroup,cgroup2,\
+                         debugfs,tracefs,securityfs,pstore,bpf,configfs,fusectl,mqueue,hugetlbfs,\
+                         autofs,nsfs"
+    )]
+    exclude_fs_types: Vec<String>,
+    /// Report every filesystem, ignoring --exclude-fs-types.
+    #[clap(long, global = true)]
+    include_all_fs: bool,
+    /// Leave filesystems smaller than this out of the disk list and totals, like `1GB` or
+    /// `512MiB`. KB, MB, GB and TB are powers of 1000; K, M, G and T, with or without `iB`, are
+    /// powers of 1024. Applies even with --include-all-fs.
+    #[clap(long, global = true, value_parser = parse_size)]
+    min_disk_size: Option<u64>,
+    /// With bits, add up_rate_bits and down_rate_bits, the traffic since the previous sample in
+    /// bits per second, as network equipment is rated. The byte counters stay as they are.
+    #[clap(long, global = true, value_enum, default_value = "bytes")]
+    net_unit: NetUnit,
+    /// Add the link speed of each interface as speed_mbps, and the share of it in use as
+    /// utilization_percent. Linux only.
+    #[clap(long, global = true)]
+    net_speed: bool,
+    /// Leave out interfaces without any traffic since the previous sample. The aggregates still
+    /// count them.
+    #[clap(long, global = true)]
+    strip_zero_rate_interfaces: bool,
+    /// Report interfaces sharing a MAC address, such as a bond and its members or VLANs and their
+    /// parent, only once, as the one with the most traffic, so the totals don't count the same
+    /// traffic twice.
+    #[clap(long, global = true)]
+    dedup_interfaces_by_mac: bool,
+    /// Report at most this many interfaces, busiest first, summing the others into an `other`
+    /// entry.
+    #[clap(long, global = true)]
+    max_interfaces: Option<usize>,
+    /// Add inode counts and usage to every disk, which costs a statvfs call per mount. Unix only.
+    #[clap(long, global = true)]
+    inodes: bool,
+    /// Report at most this many disks, busiest first, summing the others into an `other` entry.
+    #[clap(long, global = true)]
+    max_disks: Option<usize>,
+    /// Refresh network counters only every this many seconds, repeating the last ones in the
+    /// samples in between, for hosts where enumerating interfaces is costly. Byte counts and
+    /// rates then cover the time between network refreshes.
+    #[clap(long, global = true, value_parser = parse_seconds)]
+    #[serde(serialize_with = "serialize_seconds")]
+    net_refresh_interval: Option<Duration>,
+    /// Report cpu.usage as the mean over the samples of the last this many seconds instead of
+    /// the latest one alone. Per-CPU usage is left as is.
+    #[clap(long, global = true)]
+    cpu_window: Option<f32>,
+    /// Add cpus_sparkline, the usage of each CPU over the last 10 samples drawn as a sparkline.
+    #[clap(long, global = true)]
+    core_sparkline: bool,
+    /// Report the combined CPU usage of all processes as cpu.process_sum, next to cpu.usage.
+    #[clap(long, global = true)]
+    cpu_process_sum: bool,
+    /// Don't enumerate processes, leaving out the process counts and the process-based disk I/O.
+    /// Combined with `--only mem,cpu`, this takes a fast path that doesn't look at disks and
+    /// interfaces either. On a small Linux VM, `asher bench` measured that path at about 0.05 ms
+    /// per sample against about 1.9 ms for a full one, so polling several times a second is cheap.
+    #[clap(long, global = true)]
+    no_processes: bool,
+    /// Report why a subsystem couldn't be collected in the sample itself, as `<subsystem>_error`
+    /// next to the missing section, instead of as a warning.
+    #[clap(long, global = true)]
+    errors_inline: bool,
+    /// Add the measured time since the previous sample as sample_interval, to see how far the
+    /// actual sampling deviates from --interval.
+    #[clap(long, global = true)]
+    sample_jitter_report: bool,
+    /// List the names of the logged-in users in the sessions section, not just how many there
+    /// are.
+    #[clap(long, global = true)]
+    session_users: bool,
+    /// Leave the swap fields out of the memory section, for hosts that run without swap.
+    #[clap(long, global = true)]
+    no_swap: bool,
+    /// Add this many processes using the most CPU to each sample as top_processes. This makes
+    /// every sample refresh the process list with per-process CPU usage, even when processes
+    /// aren't otherwise collected, and listing them costs an allocation per process and a sort
+    /// on top, which adds up on hosts with many processes and short intervals.
+    #[clap(long, global = true, default_value = "0")]
+    top_procs: usize,
+    /// Add the minor and major page faults of each --top-procs process. Linux only.
+    #[clap(long, global = true)]
+    faults: bool,
+    /// Add collect_duration_ms, how long refreshing and building each sample took, to see how
+    /// much of --interval goes to collection.
+    #[clap(long, global = true)]
+    emit_collect_duration: bool,
+    /// Add the page cache, buffers and shared memory to mem, as `free` shows them. Linux only.
+    #[clap(long, global = true)]
+    mem_detail: bool,
+    /// Add whether the CPUs were thermally throttled since the previous sample, and how often,
+    /// to cpu. Linux only, and only where the kernel counts throttling, as for most Intel CPUs.
+    #[clap(long, global = true)]
+    cpu_throttle: bool,
+    /// Add a `host` section with the entropy available in the kernel's random pool. Linux only.
+    #[clap(long, global = true)]
+    entropy: bool,
+    /// Add a `self` section with the CPU and memory usage of asher itself.
+    #[clap(long, global = true)]
+    include_self: bool,
+    /// Exit once this many lines of output have been written, counting each line of multi-line
+    /// formats such as the table. A guard against filling a disk when --count was forgotten.
+    #[clap(long, global = true)]
+    max_lines: Option<usize>,
+    /// Don't print warnings to stderr. Errors that make asher exit are still reported.
+    #[clap(long, global = true)]
+    quiet: bool,
+    /// List disks by mount point and interfaces by name, so consecutive samples line up. The
+    /// `other` entries of --max-disks and --max-interfaces stay last.
+    #[clap(long, global = true)]
+    sort_elements: bool,
+    /// Write a `units` object with the unit of each field under the current flags, once before
+    /// the first sample. Only applies to JSON output.
+    #[clap(long, global = true)]
+    emit_units: bool,
+    /// Output format. JSON is written as one line per sample.
+    #[clap(long, global = true, value_enum, default_value = "auto")]
+    format: Format,
+    /// Round sizes and byte counts to this many significant digits, keeping them integers, so
+    /// archived samples compress better. Rates in bits and counts other than bytes are kept.
+    #[clap(long, global = true)]
+    byte_sigfigs: Option<u32>,
+    /// Pad numbers in the table to the widest they can plausibly get, so columns don't shift
+    /// between samples as values change length.
+    #[clap(long, global = true)]
+    align_output_width: bool,
+    /// Color usage percentages in the table green, yellow or red by --color-thresholds. Never
+    /// applies to JSON.
+    #[clap(long, global = true, value_enum, default_value = "auto")]
+    color: ColorChoice,
+    /// Usage percentages from which the table colors values yellow and red, as `WARN,CRIT`.
+    #[clap(long, global = true, value_delimiter = ',', default_value = "70,90")]
+    color_thresholds: Vec<f32>,
+    /// Emit only mem.used_percent, cpu.usage, disks.used_percent, and net.up_rate and
+    /// net.down_rate in bytes per second, instead of whole JSON samples. --only still applies.
+    #[clap(long, global = true)]
+    minimal: bool,
+    /// Emit JSON samples as a single-level object keyed by dotted paths such as `cpu.usage` and
+    /// `cpu.cpus.0.usage`, instead of nested objects.
+    #[clap(long, global = true)]
+    flatten: bool,
+    /// Write each sample as this line instead of in --format, replacing `{path}` placeholders
+    /// with the fields at the same dotted paths as --flatten, like `cpu={cpu.usage}`. Fields a
+    /// sample lacks are written as `-`; `{{` and `}}` stand for literal braces.
+    #[clap(long, global = true, value_parser = template::Template::parse)]
+    output_template: Option<template::Template>,
+    /// Write samples to this destination instead of --data-fd, as `TARGET[:FORMAT]` with
+    /// `stdout`, `stderr` or a file to append to, and `json`, `table` or `auto` (the default).
+    /// Repeat to write every sample to several destinations, like `--sink stdout:table --sink
+    /// samples.ndjson:json`.
+    #[clap(long, global = true, value_parser = sink::Sink::parse)]
+    sink: Vec<sink::Sink>,
+    /// Casing of the keys in the output.
+    #[clap(long, global = true, value_enum, default_value = "snake")]
+    case: Case,
+}
+
+impl Args {
+    fn wants(&self, subsystem: Subsystem) -> bool {
+        if self.no_processes && subsystem == Subsystem::Processes {
+            return false;
+        }
+        self.only.is_empty() || self.only.contains(&subsystem)
+    }
+
+    /// Whether only memory and CPU are needed, so nothing else has to be refreshed.
+    fn fast_path(&self) -> bool {
+        self.no_processes
+            && !self.only.is_empty()
+            && self
+                .only
+                .iter()
+                .all(|subsystem| matches!(subsystem, Subsystem::Mem | Subsystem::Cpu))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(SubCommand::Check {
+            cpu_delta,
+            mem_delta,
+            ..
+        }) = &self.command
+        {
+            if cpu_delta.is_none() && mem_delta.is_none() {
+                return Err("check needs --cpu-delta, --mem-delta or both".to_string());
+            }
+            for (flag, delta) in [("--cpu-delta", cpu_delta), ("--mem-delta", mem_delta)] {
+                if let Some(delta) = delta.filter(|delta| !delta.is_finite() || *delta < 0.0) {
+                    return Err(format!(
+                        "{} must be a non-negative number of percentage points, got {}",
+                        flag, delta
+                    ));
+                }
+            }
+        }
+        if let Some(SubCommand::WaitFor {
+            timeout,
+            poll_interval,
+            ..
+        }) = &self.command
+        {
+            if let Some(timeout) = timeout.filter(|&timeout| duration_from_secs(timeout).is_none())
+            {
+                return Err(format!(
+                    "--timeout must be a non-negative number of seconds, got {}",
+                    timeout
+                ));
+            }
+            if duration_from_secs(*poll_interval).is_none_or(|interval| interval.is_zero()) {
+                return Err(format!(
+                    "--poll-interval must be a positive number of seconds, got {}",
+                    poll_interval
+                ));
+            }
+        }
+        if let Some(SubCommand::Loop(loop_args)) = &self.command {
+            if !loop_args.change_epsilon.is_finite() || loop_args.change_epsilon < 0.0 {
+                return Err(format!(
+                    "--change-epsilon must be a non-negative number, got {}",
+                    loop_args.change_epsilon
+                ));
+            }
+            if !loop_args.spike_threshold.is_finite() || loop_args.spike_threshold < 0.0 {
+                return Err(format!(
+                    "--spike-threshold must be a non-negative percentage, got {}",
+                    loop_args.spike_threshold
+                ));
+            }
+            if duration_from_secs(loop_args.topology_refresh).is_none() {
+                return Err(format!(
+                    "--topology-refresh must be a non-negative number of seconds, got {}",
+                    loop_args.topology_refresh
+                ));
+            }
+            if duration_from_secs(loop_args.interval).is_none() {
+                return Err(format!(
+                    "--interval must be a non-negative number of seconds, got {}",
+                    loop_args.interval
+                ));
+            }
+            if loop_args.batch == 0 {
+                return Err("--batch must be at least 1".to_string());
+            }
+            if loop_args.count == Some(0) {
+                return Err("--count must be at least 1".to_string());
+            }
+            if let Some(timeout) = loop_args.collect_timeout {
+                if duration_from_secs(timeout).is_none_or(|timeout| timeout.is_zero()) {
+                    return Err(format!(
+                        "--collect-timeout must be a positive number of seconds, got {}",
+                        timeout
+                    ));
+                }
+            }
+            if loop_args.align && loop_args.interval == 0.0 {
+                return Err("--align requires a positive --interval".to_string());
+            }
+            if !loop_args.epoch_base.is_finite() {
+                return Err(format!(
+                    "--epoch-base must be a finite number of seconds, got {}",
+                    loop_args.epoch_base
+                ));
+            }
+            if loop_args.systemd_notify && !cfg!(target_os = "linux") {
+                return Err("--systemd-notify is only supported on Linux".to_string());
+            }
+            if loop_args.sigusr1 && !cfg!(unix) {
+                return Err("--sigusr1 is only supported on Unix".to_string());
+            }
+        }
+        match self.color_thresholds[..] {
+            [warn, crit] if warn.is_finite() && crit.is_finite() && warn <= crit => {}
+            _ => {
+                return Err(format!(
+                    "--color-thresholds must be two numbers, the lower one first, got {:?}",
+                    self.color_thresholds
+                ))
+            }
+        }
+        if self.legacy_kib_memory && self.unit_system == UnitSystem::Si {
+            return Err("--legacy-kib-memory can't be combined with --unit-system si".to_string());
+        }
+        if self.byte_sigfigs == Some(0) {
+            return Err("--byte-sigfigs must be at least 1".to_string());
+        }
+        if self.max_lines == Some(0) {
+            return Err("--max-lines must be at least 1".to_string());
+        }
+        if let Some(SubCommand::Bench { iterations: 0 }) = self.command {
+            return Err("--iterations must be at least 1".to_string());
+        }
+        if let Some(window) = self.cpu_window {
+            if duration_from_secs(window).is_none_or(|window| window.is_zero()) {
+                return Err(format!(
+                    "--cpu-window must be a positive number of seconds, got {}",
+                    window
+                ));
+            }
+        }
+        if self.no_processes && self.only.contains(&Subsystem::Processes) {
+            return Err("--no-processes can't be combined with --only processes".to_string());
+        }
+        if self.no_processes && self.cpu_process_sum {
+            return Err("--cpu-process-sum needs processes, drop --no-processes".to_string());
+        }
+        if self.no_processes && self.include_self {
+            return Err("--include-self needs processes, drop --no-processes".to_string());
+        }
+        if self.no_processes && self.top_procs > 0 {
+            return Err("--top-procs needs processes, drop --no-processes".to_string());
+        }
+        if self.faults && self.top_procs == 0 {
+            return Err("--faults applies to --top-procs, which isn't set".to_string());
+        }
+        if self.faults && !cfg!(target_os = "linux") {
+            return Err("--faults is only supported on Linux".to_string());
+        }
+        if self.inodes && !cfg!(unix) {
+            return Err("--inodes is only supported on Unix".to_string());
+        }
+        if self.net_speed && !cfg!(target_os = "linux") {
+            return Err("--net-speed is only supported on Linux".to_string());
+        }
+        if self.mem_detail && !cfg!(target_os = "linux") {
+            return Err("--mem-detail is only supported on Linux".to_string());
+        }
+        if self.cpu_throttle && !cfg!(target_os = "linux") {
+            return Err("--cpu-throttle is only supported on Linux".to_string());
+        }
+        if self.entropy && !cfg!(target_os = "linux") {
+            return Err("--entropy is only supported on Linux".to_string());
+        }
+        if !self.sink.is_empty() {
+            if self.output_template.is_some() {
+                return Err("--sink can't be combined with --output-template".to_string());
+            }
+            if self.max_lines.is_some() {
+                return Err("--sink can't be combined with --max-lines".to_string());
+            }
+        }
+        if self.minimal && self.output_template.is_some() {
+            return Err("--minimal can't be combined with --output-template".to_string());
+        }
+        if let Some(template) = &self.output_template {
+            let paths = field_paths(self);
+            if let Some(field) = template
+                .fields()
+                .find(|field| !paths.iter().any(|path| path_matches(path, field)))
+            {
+                return Err(format!(
+                    "--output-template has an unknown field {{{}}}, or one the current flags \
+                     don't produce",
+                    field
+                ));
+            }
+        }
+        if self.fail_on_missing_subsystem && self.only.is_empty() {
+            return Err("--fail-on-missing-subsystem requires --only".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Exit statuses, which mean the same for every subcommand.
+///
+/// 1 is reserved and never returned: it's left for a warning status, for health checks that find
+/// a problem short of critical, so adding one later doesn't change what the others mean.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    Ok = 0,
+    /// A check failed, such as a selftest round-trip.
+    Critical = 2,
+    /// Invalid arguments or input, such as an unreadable --input file.
+    Usage = 3,
+    /// Something couldn't be collected or represented in the output.
+    Collection = 4,
+}
+
+impl ExitCode {
+    fn exit(self) -> ! {
+        remove_pid_file();
+        std::process::exit(self as i32)
+    }
+}
+
+fn main() {
+    let args = Args::try_parse().unwrap_or_else(|err| {
+        // Printed the way clap would, but with our code for errors instead of clap's 2.
+        let _ = err.print();
+        if err.use_stderr() {
+            ExitCode::Usage.exit()
+        } else {
+            ExitCode::Ok.exit()
+        }
+    });
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    SI_UNITS.store(args.unit_system == UnitSystem::Si, Ordering::Relaxed);
+    if let Err(err) = args.validate() {
+        eprintln!("{}", err);
+        ExitCode::Usage.exit();
+    }
+
+    match args.command {
+        Some(SubCommand::Loop(ref loop_args)) => {
+            SYSTEM.get_or_init(|| new_system(&args));
+            loop_command(loop_args, &args)
+        }
+        Some(SubCommand::Validate) => print_json(&args, &args),
+        Some(SubCommand::ListSensors) => {
+            let system = System::new_with_specifics(RefreshKind::new().with_components_list());
+            let sensors: Vec<SensorStats> = system
+                .components()
+                .iter()
+                .map(|component| SensorStats {
+                    label: component.label().to_string(),
+                    temperature: component.temperature(),
+                    max: component.max(),
+                    critical: component.critical(),
+                })
+                .collect();
+            print_rows(&sensors, &args);
+        }
+        Some(SubCommand::ListNetworks) => {
+            let system = System::new_with_specifics(RefreshKind::new().with_networks_list());
+            let mut networks: Vec<NetInterfaceInfo> = system
+                .networks()
+                .iter()
+                .map(|(name, net)| NetInterfaceInfo {
+                    name: name.clone(),
+                    mac: net.mac_address().to_string(),
+                    total_up: net.total_transmitted(),
+                    total_down: net.total_received(),
+                })
+                .collect();
+            networks.sort_by(|a, b| a.name.cmp(&b.name));
+            print_rows(&networks, &args);
+        }
+        Some(SubCommand::DiskUsage { ref path }) => disk_usage_command(path, &args),
+        Some(SubCommand::Replay { ref input }) => replay_command(input, &args),
+        Some(SubCommand::Selftest) => {
+            SYSTEM.get_or_init(|| new_system(&args));
+            selftest_command(&args)
+        }
+        Some(SubCommand::Bench { iterations }) => {
+            SYSTEM.get_or_init(|| new_system(&args));
+            bench_command(iterations, &args)
+        }
+        Some(SubCommand::Check {
+            ref compare_to,
+            cpu_delta,
+            mem_delta,
+        }) => {
+            SYSTEM.get_or_init(|| new_system(&args));
+            check_command(compare_to, cpu_delta, mem_delta, &args)
+        }
+        Some(SubCommand::WaitFor {
+            ref name,
+            pid,
+            timeout,
+            poll_interval,
+            print_stats,
+        }) => wait_for_command(
+            name.as_deref(),
+            pid,
+            timeout.map(Duration::from_secs_f32),
+            Duration::from_secs_f32(poll_interval),
+            print_stats,
+            &args,
+        ),
+        None => {
+            let mut system = lock(SYSTEM.get_or_init(|| new_system(&args)));
+            startup_warmup(&args);
+            let stats = refresh_and_sample(&mut system, &args);
+            emit_units(&args);
+
+            emit(stats, &args);
+        }
+    }
+}
+
+/// Unit of every numeric field the current flags produce, keyed by dotted path with `*` for array
+/// indices.
+fn field_units(args: &Args) -> BTreeMap<String, String> {
+    let memory = if args.legacy_kib_memory {
+        MemoryUnit::Kib
+    } else {
+        args.memory_unit
+    };
+    let disk = args.memory_unit.name();
+    let mut units: Vec<(String, String)> = Vec::new();
+    if args.minimal {
+        let mut add = |field: &str, unit: &str, subsystem: Subsystem| {
+            if args.wants(subsystem) {
+                units.push((field.to_string(), unit.to_string()));
+            }
+        };
+        add("mem.used_percent", "percent", Subsystem::Mem);
+        add("cpu.usage", "percent", Subsystem::Cpu);
+        add("disks.used_percent", "percent", Subsystem::Disks);
+        add("net.up_rate", "bytes/s", Subsystem::Net);
+        add("net.down_rate", "bytes/s", Subsystem::Net);
+        return units.into_iter().collect();
+    }
+    let mut add = |fields: &[&str], unit: &str| {
+        units.extend(
+            fields
+                .iter()
+                .map(|field| (field.to_string(), unit.to_string())),
+        );
+    };
+    if args.wants(Subsystem::Mem) {
+        add(
+            &["mem.total", "mem.used", "mem.free", "mem.available"],
+            memory.name(),
+        );
+        if !args.no_swap {
+            add(
+                &["mem.total_swap", "mem.used_swap", "mem.free_swap"],
+                memory.name(),
+            );
+        }
+        if args.mem_detail {
+            add(&["mem.cached", "mem.buffers", "mem.shared"], memory.name());
+        }
+    }
+    if args.wants(Subsystem::Cpu) {
+        add(
+            &[
+                "cpu.usage",
+                "cpu.cpus.*.usage",
+                "cpu.packages.*.usage",
+                "cpu.times.*",
+                "cpu.steal_percent",
+            ],
+            "percent",
+        );
+        add(
+            &[
+                "cpu.packages.*.id",
+                "cpu.packages.*.cpu_count",
+                "cpu.allowed_cpus",
+            ],
+            "count",
+        );
+        if args.cpu_process_sum {
+            add(&["cpu.process_sum"], "percent");
+        }
+        if args.cpu_throttle {
+            add(&["cpu.throttle_events"], "count");
+        }
+    }
+    if args.wants(Subsystem::Disks) {
+        add(
+            &[
+                "disks.total",
+                "disks.free",
+                "disks.used",
+                "disks.read",
+                "disks.write",
+                "disks.read_total",
+                "disks.write_total",
+                "disks.disks.*.total",
+                "disks.disks.*.free",
+                "disks.disks.*.used",
+                "disks.disks.*.read",
+                "disks.disks.*.write",
+                "disks.root_disk.total",
+                "disks.root_disk.free",
+                "disks.root_disk.used",
+                "disks.root_disk.read",
+                "disks.root_disk.write",
+            ],
+            disk,
+        );
+        add(
+            &["disks.read_rate", "disks.write_rate"],
+            &format!("{}/s", disk),
+        );
+        if args.inodes {
+            add(
+                &[
+                    "disks.disks.*.inodes_total",
+                    "disks.disks.*.inodes_used",
+                    "disks.disks.*.inodes_free",
+                    "disks.root_disk.inodes_total",
+                    "disks.root_disk.inodes_used",
+                    "disks.root_disk.inodes_free",
+                ],
+                "count",
+            );
+            add(
+                &[
+                    "disks.disks.*.inodes_used_percent",
+                    "disks.root_disk.inodes_used_percent",
+                ],
+                "percent",
+            );
+        }
+    }
+    if args.wants(Subsystem::Net) {
+        add(
+            &[
+                "net.total_up",
+                "net.total_down",
+                "net.up",
+                "net.down",
+                "net.interfaces.*.total_up",
+                "net.interfaces.*.total_down",
+                "net.interfaces.*.up",
+                "net.interfaces.*.down",
+            ],
+            "bytes",
+        );
+        if args.net_unit == NetUnit::Bits {
+            add(
+                &[
+                    "net.up_rate_bits",
+                    "net.down_rate_bits",
+                    "net.interfaces.*.up_rate_bits",
+                    "net.interfaces.*.down_rate_bits",
+                ],
+                "bits/s",
+            );
+        }
+        if args.net_speed {
+            add(&["net.interfaces.*.speed_mbps"], "mbit/s");
+            add(&["net.interfaces.*.utilization_percent"], "percent");
+        }
+    }
+    if args.wants(Subsystem::Processes) {
+        add(&["processes.count", "processes.total_threads"], "count");
+    }
+    if args.wants(Subsystem::Sessions) {
+        add(&["sessions.users", "sessions.sessions"], "count");
+    }
+    if args.include_self {
+        add(&["self.cpu_usage"], "percent");
+        add(&["self.memory"], memory.name());
+    }
+    if args.entropy {
+        add(&["host.entropy_avail"], "bits");
+    }
+    if args.top_procs > 0 {
+        add(&["top_processes.*.cpu_usage"], "percent");
+        add(&["top_processes.*.memory"], memory.name());
+        if args.faults {
+            add(
+                &[
+                    "top_processes.*.minor_faults",
+                    "top_processes.*.major_faults",
+                ],
+                "count",
+            );
+        }
+    }
+    if args.sample_jitter_report {
+        add(&["sample_interval"], "seconds");
+    }
+    if args.emit_collect_duration {
+        add(&["collect_duration_ms"], "milliseconds");
+    }
+    units.into_iter().collect()
+}
+
+/// Whether samples are written as JSON to --data-fd, rather than as a table, through
+/// --output-template or to --sink destinations.
+fn writes_json(args: &Args) -> bool {
+    args.sink.is_empty()
+        && args.output_template.is_none()
+        && args.format.resolve(args.data_fd) != Format::Table
+}
+
+/// Every field path the current flags can produce, numeric or not, with `*` for array indices.
+fn field_paths(args: &Args) -> Vec<String> {
+    let mut paths: Vec<String> = field_units(args).into_keys().collect();
+    let mut add = |subsystem: Option<Subsystem>, fields: &[&str]| {
+        if subsystem.is_none_or(|subsystem| args.wants(subsystem)) {
+            paths.extend(fields.iter().map(|field| field.to_string()));
+        }
+    };
+    add(
+        Some(Subsystem::Cpu),
+        &["cpu.cpus_sparkline.*", "cpu.throttling"],
+    );
+    add(
+        Some(Subsystem::Disks),
+        &[
+            "disks.disks.*.name",
+            "disks.disks.*.mount_point",
+            "disks.disks.*.fs_type",
+            "disks.root_disk.name",
+            "disks.root_disk.mount_point",
+            "disks.root_disk.fs_type",
+            "disks.busiest_disk_io",
+        ],
+    );
+    add(Some(Subsystem::Net), &["net.interfaces.*.name"]);
+    add(Some(Subsystem::Sessions), &["sessions.usernames.*"]);
+    add(
+        None,
+        &[
+            "self.pid",
+            "top_processes.*.pid",
+            "top_processes.*.name",
+            "spikes.*",
+            "stale",
+            "on_demand",
+        ],
+    );
+    for &subsystem in Subsystem::value_variants() {
+        if args.wants(subsystem) {
+            paths.push(format!("{}_error", subsystem.name()));
+        }
+    }
+    paths
+}
+
+/// Whether `path` matches `pattern`, where a `*` segment in the pattern matches any one segment.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('.');
+    let mut path = path.split('.');
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment)) if expected == "*" || expected == segment => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Writes the `units` object for --emit-units, which JSON consumers read before the samples.
+fn emit_units(args: &Args) {
+    if !args.emit_units {
+        return;
+    }
+    let units = serde_json::json!({
+        "units": field_units(args),
+        "unit_system": args.unit_system,
+    });
+    if writes_json(args) {
+        print_json(&units, args);
+    }
+    for sink in &args.sink {
+        if sink.format() == Format::Json {
+            write_sink(sink, &json_line(&units, args));
+        }
+    }
+}
+
+fn emit(stats: SysStats, args: &Args) {
+    print_stats(&prepare(stats, args), args);
+}
+
+/// Fails with `ExitCode::Collection` if --fail-on-missing-subsystem is set and a subsystem named in
+/// --only produced no data.
+fn require_subsystems(stats: &SysStats, args: &Args) -> Result<(), ExitCode> {
+    if args.fail_on_missing_subsystem {
+        if let Some(subsystem) = args.only.iter().find(|s| stats.is_missing(**s)) {
+            eprintln!("no data collected for requested subsystem {:?}", subsystem);
+            return Err(ExitCode::Collection);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the output selection, filters and units to a freshly collected sample.
+fn prepare(mut stats: SysStats, args: &Args) -> SysStats {
+    if !args.only.is_empty() {
+        stats.retain(&args.only);
+    }
+    if let Err(code) = require_subsystems(&stats, args) {
+        code.exit();
+    }
+
+    if !args.errors_inline {
+        for (key, err) in std::mem::take(&mut stats.errors) {
+            warning!("{}: {}", key.trim_end_matches("_error"), err);
+        }
+    }
+    if args.no_processes {
+        stats.processes = None;
+    }
+    if !args.session_users {
+        if let Some(sessions) = &mut stats.sessions {
+            sessions.usernames = None;
+        }
+    }
+    if let (true, Some(mem)) = (args.no_swap, &mut stats.mem) {
+        mem.total_swap = None;
+        mem.used_swap = None;
+        mem.free_swap = None;
+    }
+    if !args.include_self {
+        stats.own = None;
+    }
+    if args.sample_jitter_report {
+        stats.sample_interval = Some(refresh_interval().as_secs_f64());
+    }
+    if let (true, Some(cpu)) = (args.core_sparkline, &mut stats.cpu) {
+        cpu.cpus_sparkline = Some(core_sparklines(&cpu.cpus));
+    }
+    if let (Some(window), Some(cpu)) = (args.cpu_window, &mut stats.cpu) {
+        cpu.usage = windowed_cpu_usage(cpu.usage, Duration::from_secs_f32(window));
+    }
+    if !args.cpu_process_sum {
+        if let Some(cpu) = &mut stats.cpu {
+            cpu.process_sum = None;
+        }
+    }
+    if !args.interfaces.is_empty() {
+        if let Some(net) = &mut stats.net {
+            net.select_interfaces(&args.interfaces, args.missing_interfaces);
+        }
+    }
+    if !args.include_all_fs {
+        if let Some(disks) = &mut stats.disks {
+            disks.exclude_disks(|disk| args.exclude_fs_types.contains(&disk.fs_type));
+        }
+    }
+    if let (Some(min), Some(disks)) = (args.min_disk_size, &mut stats.disks) {
+        disks.exclude_disks(|disk| disk.total < min);
+    }
+    if let (true, Some(net)) = (args.dedup_interfaces_by_mac, &mut stats.net) {
+        net.dedup_interfaces_by_mac();
+    }
+    if let (Some(max), Some(net)) = (args.max_interfaces, &mut stats.net) {
+        net.cap_interfaces(max);
+    }
+    #[cfg(unix)]
+    if let (true, Some(disks)) = (args.inodes, &mut stats.disks) {
+        for disk in &mut disks.disks {
+            if let Some((total, free)) = unix::inode_counts(&disk.mount_point) {
+                disk.set_inodes(total, free);
+            }
+        }
+    }
+    if let Some(disks) = &mut stats.disks {
+        // Before capping, which may fold these disks into `other`.
+        disks.find_root_disk();
+        disks.find_busiest_disk_io();
+    }
+    if let (Some(max), Some(disks)) = (args.max_disks, &mut stats.disks) {
+        disks.cap_disks(max);
+    }
+    if args.sort_elements {
+        if let Some(disks) = &mut stats.disks {
+            disks.sort_disks();
+        }
+        if let Some(net) = &mut stats.net {
+            net.sort_interfaces();
+        }
+    }
+    debug_assert!(stats.net.as_ref().is_none_or(NetStats::totals_match));
+    if let (NetUnit::Bits, Some(net)) = (args.net_unit, &mut stats.net) {
+        net.add_bit_rates(net_refresh_interval());
+    }
+    if let (true, Some(net)) = (args.net_speed, &mut stats.net) {
+        net.add_link_speeds(net_refresh_interval());
+    }
+    if let (true, Some(net)) = (args.strip_zero_rate_interfaces, &mut stats.net) {
+        // After the totals check: the aggregates deliberately still include these.
+        net.interfaces
+            .retain(|interface| interface.up != 0 || interface.down != 0);
+    }
+    let divisor = args.memory_unit.divisor();
+    if divisor != 1 {
+        if let Some(mem) = &mut stats.mem {
+            mem.map_sizes(|size| size / divisor);
+        }
+        if let Some(disks) = &mut stats.disks {
+            disks.map_sizes(|size| size / divisor);
+        }
+        if let Some(own) = &mut stats.own {
+            own.memory /= divisor;
+        }
+        for process in stats.top_processes.iter_mut().flatten() {
+            process.memory /= divisor;
+        }
+    }
+    if args.legacy_kib_memory {
+        if let Some(mem) = &mut stats.mem {
+            mem.map_sizes(|size| size / MemoryUnit::Kib.divisor());
+        }
+        if let Some(own) = &mut stats.own {
+            own.memory /= MemoryUnit::Kib.divisor();
+        }
+        for process in stats.top_processes.iter_mut().flatten() {
+            process.memory /= MemoryUnit::Kib.divisor();
+        }
+    }
+    if let Some(digits) = args.byte_sigfigs {
+        let round = |size: u64| round_to_sigfigs(size, digits);
+        if let Some(mem) = &mut stats.mem {
+            mem.map_sizes(round);
+        }
+        if let Some(disks) = &mut stats.disks {
+            disks.map_sizes(round);
+        }
+        if let Some(net) = &mut stats.net {
+            net.map_bytes(round);
+        }
+        if let Some(own) = &mut stats.own {
+            own.memory = round(own.memory);
+        }
+        for process in stats.top_processes.iter_mut().flatten() {
+            process.memory = round(process.memory);
+        }
+    }
+    stats
+}
+
+/// Rounds `value` to its `digits` most significant decimal digits, like 16_234_567 to
+/// 16_200_000 for 3.
+fn round_to_sigfigs(value: u64, digits: u32) -> u64 {
+    let Some(excess) = (value.checked_ilog10().unwrap_or(0) + 1).checked_sub(digits) else {
+        return value;
+    };
+    let step = 10u128.pow(excess);
+    let rounded = (value as u128 + step / 2) / step * step;
+    u64::try_from(rounded).unwrap_or(value)
+}
+
+fn print_stats(stats: &SysStats, args: &Args) {
+    if !args.sink.is_empty() {
+        for sink in &args.sink {
+            let text = match sink.format() {
+                Format::Table => table::render_stats(stats, args, sink.is_terminal()),
+                _ => sample_json(stats, args),
+            };
+            write_sink(sink, &text);
+        }
+        return;
+    }
+    if let Some(template) = &args.output_template {
+        write_data(&template.render(&flat_sample(stats)), args);
+        return;
+    }
+    match args.format.resolve(args.data_fd) {
+        Format::Table => write_data(
+            &table::render_stats(stats, args, args.data_fd.is_terminal()),
+            args,
+        ),
+        _ => write_data(&sample_json(stats, args), args),
+    }
+}
+
+/// Exits if `stats` has a number JSON can't represent and --strict-json is set.
+fn check_finite(stats: &SysStats, args: &Args) {
+    if args.strict_json {
+        if let Some(field) = stats.non_finite_field() {
+            eprintln!("{} is not a finite number", field);
+            ExitCode::Collection.exit();
+        }
+    }
+}
+
+/// `stats` serialized as one line of JSON, flattened with --flatten.
+fn sample_json(stats: &SysStats, args: &Args) -> String {
+    check_finite(stats, args);
+    match (args.minimal, args.flatten) {
+        (false, false) => json_line(stats, args),
+        (false, true) => json_line(&flat_sample(stats), args),
+        (true, false) => json_line(&MinimalStats::from(stats), args),
+        (true, true) => json_line(&flat_sample(&MinimalStats::from(stats)), args),
+    }
+}
+
+/// `stats` as a single-level object keyed by dotted path.
+fn flat_sample(stats: &impl Serialize) -> serde_json::Map<String, serde_json::Value> {
+    // Going through the text keeps f32 fields as short as in nested output, which `to_value`
+    // would widen to f64 digits.
+    let value = serde_json::from_str(&serde_json::to_string(stats).unwrap()).unwrap();
+    let mut flat = serde_json::Map::new();
+    flatten_keys(&value, "", &mut flat);
+    flat
+}
+
+fn print_rows<T: Serialize + table::TableRow>(rows: &[T], args: &Args) {
+    match args.format.resolve(args.data_fd) {
+        Format::Table => write_data(&table::render_rows(rows), args),
+        _ => print_json(&rows, args),
+    }
+}
+
+fn print_row<T: Serialize + table::TableRow>(row: &T, args: &Args) {
+    match args.format.resolve(args.data_fd) {
+        Format::Table => write_data(&table::render_rows(std::slice::from_ref(row)), args),
+        _ => print_json(row, args),
+    }
+}
+
+fn print_json(value: &impl Serialize, args: &Args) {
+    write_data(&json_line(value, args), args);
+}
+
+fn json_line(value: &impl Serialize, args: &Args) -> String {
+    match args.case {
+        Case::Snake => serde_json::to_string(value).unwrap(),
+        Case::Camel => {
+            let mut value = serde_json::to_value(value).unwrap();
+            camel_case_keys(&mut value);
+            value.to_string()
+        }
+    }
+}
+
+/// Writes `text` as one or more lines, then exits if that reaches --max-lines, cutting off the
+/// lines past it.
+fn write_data(text: &str, args: &Args) {
+    let Some(max) = args.max_lines else {
+        match args.data_fd {
+            DataFd::Stdout => println!("{}", text),
+            DataFd::Stderr => eprintln!("{}", text),
+        }
+        return;
+    };
+    let remaining = max - LINES_WRITTEN.load(Ordering::Relaxed);
+    let lines: Vec<&str> = text.split('\n').take(remaining).collect();
+    let text = lines.join("\n");
+    match args.data_fd {
+        DataFd::Stdout => println!("{}", text),
+        DataFd::Stderr => eprintln!("{}", text),
+    }
+    if LINES_WRITTEN.fetch_add(lines.len(), Ordering::Relaxed) + lines.len() >= max {
+        ExitCode::Ok.exit();
+    }
+}
+
+/// Writes `text` to `sink`, warning instead of exiting when that fails so the other sinks keep
+/// getting samples.
+fn write_sink(sink: &sink::Sink, text: &str) {
+    if let Err(error) = sink.write(text) {
+        warning!("failed to write to sink {}: {}", sink, error);
+    }
+}
+
+/// Parses a size in bytes with an optional unit suffix, like `1GB`, `1.5 GiB` or `4096`.
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("{:?} doesn't start with a number", text))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        _ => return Err(format!("unknown size unit {:?}", unit.trim())),
+    };
+    let bytes = number * multiplier as f64;
+    // `u64::MAX as f64` rounds up to 2^64, which is already out of range.
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("{:?} is too large", text));
+    }
+    Ok(bytes as u64)
+}
+
+fn loop_command(loop_args: &LoopArgs, args: &Args) {
+    let mut interval = Duration::from_secs_f32(loop_args.interval);
+    if loop_args.systemd_notify {
+        systemd_notify("READY=1");
+    }
+    #[cfg(target_os = "linux")]
+    let watchdog = loop_args
+        .systemd_notify
+        .then(linux::watchdog_interval)
+        .flatten();
+    #[cfg(not(target_os = "linux"))]
+    let watchdog = None;
+
+    if let Some(path) = &loop_args.pid_file {
+        write_pid_file(path);
+        #[cfg(unix)]
+        unix::catch_interrupts();
+    }
+    if loop_args.prime {
+        let mut system = lock(SYSTEM.get().unwrap());
+        startup_warmup(args);
+        // Building a sample moves the per-sample baselines (CPU times, disk I/O) forward.
+        let _ = refresh_and_sample(&mut system, args);
+    }
+    for _ in 0..loop_args.warmup_samples {
+        sleep_with_watchdog(interval, watchdog);
+        // Collecting moves the per-sample baselines (CPU times, disk I/O) forward. The history
+        // behind --cpu-window and --core-sparkline is fed here instead of through prepare, which
+        // could warn or exit over a sample that is never written.
+        let stats = collect(args, false);
+        if let Some(cpu) = &stats.cpu {
+            if args.core_sparkline {
+                core_sparklines(&cpu.cpus);
+            }
+            if let Some(window) = args.cpu_window {
+                windowed_cpu_usage(cpu.usage, Duration::from_secs_f32(window));
+            }
+        }
+    }
+
+    let topology_refresh = Duration::from_secs_f32(loop_args.topology_refresh);
+    let collect_timeout = loop_args.collect_timeout.map(Duration::from_secs_f32);
+    let shared_args = Arc::new(args.clone());
+    let mut last_topology_refresh = Instant::now();
+    let mut pending: Option<mpsc::Receiver<SysStats>> = None;
+    let mut last_collected: Option<SysStats> = None;
+    let mut last_sample: Option<serde_json::Value> = None;
+    let mut last_emitted: Option<serde_json::Value> = None;
+    let mut interval_file_modified = None;
+    // Batches only apply to JSON; other formats are written sample by sample.
+    let batch_size = if writes_json(args) {
+        loop_args.batch
+    } else {
+        1
+    };
+    let mut batch: Vec<String> = Vec::with_capacity(batch_size);
+    let mut emitted = 0;
+    let mut drop_next = loop_args.drop_first_sample;
+    emit_units(args);
+    if batch_size > 1 {
+        // Flush the last, partial batch on Ctrl-C instead of losing it.
+        #[cfg(unix)]
+        unix::catch_interrupts();
+    }
+    #[cfg(unix)]
+    if loop_args.sigusr1 {
+        unix::catch_sample_requests();
+    }
+    // End of the scheduled sleep that a --sigusr1 sample cut short.
+    let mut resume: Option<Instant> = None;
+    loop {
+        if let Some(path) = &loop_args.interval_file {
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified != interval_file_modified {
+                interval_file_modified = modified;
+                match read_interval_file(path, loop_args.align) {
+                    Ok(new_interval) => interval = new_interval,
+                    Err(err) => warning!(
+                        "{}: {}, keeping the interval at {}s",
+                        path.display(),
+                        err,
+                        interval.as_secs_f32()
+                    ),
+                }
+            }
+        }
+        let deadline = match resume.take() {
+            Some(deadline) => deadline,
+            None if loop_args.align => {
+                Instant::now() + until_aligned(interval, loop_args.epoch_base)
+            }
+            None => Instant::now() + interval,
+        };
+        sleep_with_watchdog(deadline.saturating_duration_since(Instant::now()), watchdog);
+        if interrupted() {
+            break;
+        }
+        let on_demand = take_sample_request() && Instant::now() < deadline;
+        if on_demand {
+            resume = Some(deadline);
+        }
+        let mut topology_due = || {
+            let due =
+                !topology_refresh.is_zero() && last_topology_refresh.elapsed() >= topology_refresh;
+            if due {
+                last_topology_refresh = Instant::now();
+            }
+            due
+        };
+        let mut stats = match collect_timeout {
+            None => prepare(collect(args, topology_due()), args),
+            Some(timeout) => {
+                // A collection that timed out keeps running; wait for it rather than piling up
+                // more workers behind the same lock.
+                let receiver = pending.get_or_insert_with(|| {
+                    let (sender, receiver) = mpsc::channel();
+                    let args = Arc::clone(&shared_args);
+                    let refresh_topology = topology_due();
+                    thread::spawn(move || {
+                        let _ = sender.send(collect(&args, refresh_topology));
+                    });
+                    receiver
+                });
+                match receiver.recv_timeout(timeout) {
+                    Ok(stats) => {
+                        pending = None;
+                        let stats = prepare(stats, args);
+                        last_collected = Some(stats.clone());
+                        stats
+                    }
+                    Err(err) => {
+                        if err == mpsc::RecvTimeoutError::Disconnected {
+                            pending = None;
+                        }
+                        let Some(last) = &last_collected else {
+                            warning!("collection timed out with no earlier sample to reuse");
+                            continue;
+                        };
+                        warning!("collection timed out, reusing the previous sample");
+                        SysStats {
+                            stale: true,
+                            ..last.clone()
+                        }
+                    }
+                }
+            }
+        };
+
+        if loop_args.spike_detect {
+            let value = serde_json::to_value(&stats).unwrap();
+            let mut spikes = Vec::new();
+            if let Some(last) = &last_sample {
+                find_spikes(last, &value, "", loop_args.spike_threshold, &mut spikes);
+            }
+            stats.spikes = Some(spikes);
+            last_sample = Some(value);
+        }
+        if std::mem::take(&mut drop_next) {
+            continue;
+        }
+        stats.on_demand = on_demand;
+        if loop_args.emit_on_change {
+            let value = serde_json::to_value(&stats).unwrap();
+            if !on_demand
+                && last_emitted
+                    .as_ref()
+                    .is_some_and(|last| !differs(last, &value, loop_args.change_epsilon))
+            {
+                continue;
+            }
+            last_emitted = Some(value);
+        }
+        if batch_size > 1 {
+            if args.strict_json && stats.non_finite_field().is_some() && !batch.is_empty() {
+                // This sample fails --strict-json; the ones already batched are still written.
+                write_data(&format!("[{}]", batch.join(",")), args);
+                batch.clear();
+            }
+            batch.push(sample_json(&stats, args));
+            if batch.len() >= batch_size || on_demand {
+                write_data(&format!("[{}]", batch.join(",")), args);
+                batch.clear();
+            }
+        } else {
+            print_stats(&stats, args);
+        }
+        emitted += 1;
+        if loop_args.count.is_some_and(|count| emitted >= count) {
+            break;
+        }
+    }
+    if !batch.is_empty() {
+        write_data(&format!("[{}]", batch.join(",")), args);
+    }
+    remove_pid_file();
+}
+
+/// Writes the PID of asher to `path` for --pid-file, exiting if it names another running asher.
+/// A file left behind by one that didn't get to remove it is overwritten.
+fn write_pid_file(path: &std::path::Path) {
+    let previous = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.trim().parse::<u32>().ok());
+    if let Some(pid) = previous {
+        if pid != std::process::id() && process_running(pid) {
+            eprintln!(
+                "{} names PID {}, which is still running; is asher already running?",
+                path.display(),
+                pid
+            );
+            ExitCode::Usage.exit();
+        }
+        warning!("overwriting stale {}, from PID {}", path.display(), pid);
+    }
+    if let Err(err) = std::fs::write(path, format!("{}\n", std::process::id())) {
+        eprintln!("can't write {}: {}", path.display(), err);
+        ExitCode::Usage.exit();
+    }
+    PID_FILE.set(path.to_path_buf()).unwrap();
+}
+
+fn remove_pid_file() {
+    if let Some(path) = PID_FILE.get() {
+        if let Err(err) = std::fs::remove_file(path) {
+            warning!("failed to remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Finds the disk with the longest mount point that is a prefix of `path` and reports it.
+fn disk_usage_command(path: &std::path::Path, args: &Args) {
+    let resolved = match std::fs::canonicalize(path) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            eprintln!("can't resolve {}: {}", path.display(), err);
+            ExitCode::Usage.exit();
+        }
+    };
+    let system = System::new_with_specifics(RefreshKind::new().with_disks_list());
+    let Some(disk) = system
+        .disks()
+        .iter()
+        .filter(|disk| resolved.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+    else {
+        eprintln!("no filesystem found containing {}", resolved.display());
+        ExitCode::Collection.exit();
+    };
+    let divisor = args.memory_unit.divisor();
+    let used = disk.total_space() - disk.available_space();
+    let usage = DiskUsage {
+        path: resolved.to_string_lossy().to_string(),
+        mount_point: disk.mount_point().to_string_lossy().to_string(),
+        fs_type: String::from_utf8_lossy(disk.file_system()).to_string(),
+        total: disk.total_space() / divisor,
+        used: used / divisor,
+        free: disk.available_space() / divisor,
+        used_percent: if disk.total_space() == 0 {
+            0.0
+        } else {
+            used as f32 * 100.0 / disk.total_space() as f32
+        },
+        unit: args.memory_unit,
+    };
+    print_row(&usage, args);
+}
+
+/// Re-emits each sample of `input`, including the ones in --batch arrays. Lines that aren't
+/// samples, such as the --emit-units header, are skipped with a warning, or fail the replay with
+/// --strict-json.
+fn replay_command(input: &std::path::Path, args: &Args) {
+    use std::io::BufRead;
+
+    let reader: Box<dyn BufRead> = if input == std::path::Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        match std::fs::File::open(input) {
+            Ok(file) => Box::new(std::io::BufReader::new(file)),
+            Err(err) => {
+                eprintln!("can't open {}: {}", input.display(), err);
+                ExitCode::Usage.exit();
+            }
+        }
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("can't read {}: {}", input.display(), err);
+                ExitCode::Usage.exit();
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_samples(&line) {
+            Ok(samples) => samples.iter().for_each(|stats| print_stats(stats, args)),
+            Err(err) if args.strict_json => {
+                eprintln!("line {} is not a sample: {}", index + 1, err);
+                ExitCode::Usage.exit();
+            }
+            Err(err) => warning!(
+                "skipping line {}, which is not a sample: {}",
+                index + 1,
+                err
+            ),
+        }
+    }
+}
+
+/// The samples on one line of output, either a single sample or a --batch array of them.
+fn parse_samples(line: &str) -> serde_json::Result<Vec<SysStats>> {
+    if line.trim_start().starts_with('[') {
+        serde_json::from_str(line)
+    } else {
+        serde_json::from_str(line).map(|stats| vec![stats])
+    }
+}
+
+/// Serializes a fresh sample the ways replay reads samples back, as a single line and inside a
+/// --batch array, and reports the fields that didn't survive the round trip.
+fn selftest_command(args: &Args) {
+    let stats = {
+        let mut system = lock(SYSTEM.get().unwrap());
+        startup_warmup(args);
+        prepare(refresh_and_sample(&mut system, args), args)
+    };
+    let json = serde_json::to_string(&stats).unwrap();
+    let reread = |text: &str, batch: bool| -> Result<String, serde_json::Error> {
+        let stats = if batch {
+            serde_json::from_str::<Vec<SysStats>>(text)?.remove(0)
+        } else {
+            serde_json::from_str::<SysStats>(text)?
+        };
+        Ok(serde_json::to_string(&stats).unwrap())
+    };
+    let mut results = Vec::new();
+    for (name, result) in [
+        ("json", reread(&json, false)),
+        ("json batch", reread(&format!("[{}]", json), true)),
+    ] {
+        let changed = match result {
+            Ok(reread) => {
+                let mut before = serde_json::Map::new();
+                let mut after = serde_json::Map::new();
+                flatten_keys(&serde_json::from_str(&json).unwrap(), "", &mut before);
+                flatten_keys(&serde_json::from_str(&reread).unwrap(), "", &mut after);
+                let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                keys.retain(|key| before.get(*key) != after.get(*key));
+                keys.iter()
+                    .map(|key| format!("{} changed", key))
+                    .collect::<Vec<_>>()
+            }
+            Err(err) => vec![format!("can't be read back: {}", err)],
+        };
+        results.push(SelftestResult {
+            form: name,
+            passed: changed.is_empty(),
+            problems: changed,
+        });
+    }
+    print_rows(&results, args);
+    if results.iter().any(|result| !result.passed) {
+        ExitCode::Critical.exit();
+    }
+}
+
+/// Compares a fresh sample with the one in `baseline`, printing each compared metric and exiting
+/// with 2 if any grew by more than its delta.
+fn check_command(
+    baseline: &std::path::Path,
+    cpu_delta: Option<f32>,
+    mem_delta: Option<f32>,
+    args: &Args,
+) {
+    let baseline_stats = read_baseline(baseline);
+    let stats = {
+        let mut system = lock(SYSTEM.get().unwrap());
+        startup_warmup(args);
+        prepare(refresh_and_sample(&mut system, args), args)
+    };
+    let cpu_usage = |stats: &SysStats| stats.cpu.as_ref().map(|cpu| cpu.usage);
+    let mem_usage = |stats: &SysStats| {
+        stats
+            .mem
+            .as_ref()
+            .filter(|mem| mem.total > 0)
+            .map(|mem| mem.used as f32 * 100.0 / mem.total as f32)
+    };
+    let mut results = Vec::new();
+    for (name, delta, before, now) in [
+        (
+            "cpu usage",
+            cpu_delta,
+            cpu_usage(&baseline_stats),
+            cpu_usage(&stats),
+        ),
+        (
+            "mem usage",
+            mem_delta,
+            mem_usage(&baseline_stats),
+            mem_usage(&stats),
+        ),
+    ] {
+        let Some(delta) = delta else {
+            continue;
+        };
+        let Some(before) = before else {
+            eprintln!("{} has no {} to compare to", baseline.display(), name);
+            ExitCode::Usage.exit();
+        };
+        let Some(now) = now else {
+            eprintln!("couldn't collect {}", name);
+            ExitCode::Collection.exit();
+        };
+        let growth = now - before;
+        results.push(CheckResult {
+            metric: name,
+            passed: growth <= delta,
+            usage: now,
+            baseline: before,
+            growth,
+            max_growth: delta,
+        });
+    }
+    print_rows(&results, args);
+    if results.iter().any(|result| !result.passed) {
+        ExitCode::Critical.exit();
+    }
+}
+
+/// The first sample in `path`, skipping lines that aren't one, such as --emit-units metadata.
+fn read_baseline(path: &std::path::Path) -> SysStats {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("can't read {}: {}", path.display(), err);
+            ExitCode::Usage.exit();
+        }
+    };
+    let sample = content
+        .lines()
+        .find_map(|line| parse_samples(line).ok()?.into_iter().next());
+    let Some(sample) = sample else {
+        eprintln!("{} has no sample", path.display());
+        ExitCode::Usage.exit();
+    };
+    sample
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LatencyStats {
+    mean_ms: f64,
+    p50_ms: f64,
+    p99_ms: f64,
+}
+
+impl LatencyStats {
+    fn from_samples(samples: &mut [Duration]) -> Self {
+        samples.sort();
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| {
+            samples
+                .get(((samples.len() - 1) as f64 * p).round() as usize)
+                .map_or(0.0, |&duration| ms(duration))
+        };
+        Self {
+            mean_ms: samples.iter().map(|&duration| ms(duration)).sum::<f64>()
+                / samples.len() as f64,
+            p50_ms: percentile(0.5),
+            p99_ms: percentile(0.99),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BenchReport {
+    iterations: usize,
+    mem: LatencyStats,
+    cpu: LatencyStats,
+    components: LatencyStats,
+    processes: LatencyStats,
+    disks: LatencyStats,
+    net: LatencyStats,
+    build: LatencyStats,
+    total: LatencyStats,
+}
+
+/// Polls the process list until a process named `name`, or else with PID `pid`, shows up or
+/// `timeout` passes.
+fn wait_for_command(
+    name: Option<&str>,
+    pid: Option<u32>,
+    timeout: Option<Duration>,
+    poll_interval: Duration,
+    print_stats: bool,
+    args: &Args,
+) {
+    // Past the end of `Instant`, there is no deadline to speak of.
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let mut system = System::new();
+    loop {
+        system.refresh_processes_specifics(ProcessRefreshKind::new().with_cpu());
+        let found = match (name, pid) {
+            (Some(name), _) => system
+                .processes()
+                .values()
+                .filter(|process| process.name() == name)
+                .min_by_key(|process| process.pid()),
+            (None, Some(pid)) => system.process(Pid::from_u32(pid)),
+            (None, None) => unreachable!("clap requires --name or --pid"),
+        };
+        if let Some(process) = found {
+            if print_stats {
+                print_row(&TopProcess::from(process), args);
+            }
+            return;
+        }
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            match (name, pid) {
+                (Some(name), _) => eprintln!("no process named {} appeared", name),
+                (None, Some(pid)) => eprintln!("no process with PID {} appeared", pid),
+                (None, None) => {}
+            }
+            ExitCode::Critical.exit();
+        }
+        let next = now + poll_interval;
+        thread::sleep(deadline.map_or(next, |deadline| deadline.min(next)) - now);
+    }
+}
+
+/// Runs the same refreshes as `refresh` one by one, timing each of them along with building
+/// and preparing the sample.
+fn bench_command(iterations: usize, args: &Args) {
+    let mut system = lock(SYSTEM.get().unwrap());
+    let mut timings: [Vec<Duration>; 8] = Default::default();
+    let kind = refresh_kind(args);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        // Steps that `refresh` skips for these arguments are recorded as taking no time.
+        let mut time = |index: usize, run: bool, step: &mut dyn FnMut()| {
+            let step_start = Instant::now();
+            if run {
+                step();
+            }
+            timings[index].push(step_start.elapsed());
+        };
+        time(0, kind.memory(), &mut || system.refresh_memory());
+        time(1, kind.cpu().is_some(), &mut || {
+            system.refresh_cpu_specifics(kind.cpu().unwrap())
+        });
+        time(2, kind.components(), &mut || system.refresh_components());
+        time(3, kind.processes().is_some(), &mut || {
+            system.refresh_processes_specifics(kind.processes().unwrap())
+        });
+        time(4, kind.disks(), &mut || system.refresh_disks());
+        time(5, kind.networks(), &mut || system.refresh_networks());
+        time(6, true, &mut || {
+            prepare(sample(&system, args), args);
+        });
+        timings[7].push(start.elapsed());
+    }
+
+    let [mem, cpu, components, processes, disks, net, build, total] =
+        timings.map(|mut samples| LatencyStats::from_samples(&mut samples));
+    let report = BenchReport {
+        iterations,
+        mem,
+        cpu,
+        components,
+        processes,
+        disks,
+        net,
+        build,
+        total,
+    };
+    print_json(&report, args);
+}
+
+fn camel_case_keys(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    camel_case_keys(&mut value);
+                    let mut parts = key.split('_');
+                    let mut camel = parts.next().unwrap_or_default().to_string();
+                    for part in parts {
+                        let mut chars = part.chars();
+                        if let Some(first) = chars.next() {
+                            camel.extend(first.to_uppercase());
+                            camel.push_str(chars.as_str());
+                        }
+                    }
+                    (camel, value)
+                })
+                .collect();
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+/// Copies the leaves of `value` into `flat`, keyed by their dotted paths with array indices as
+/// path segments, the same paths --spike-detect reports.
+fn flatten_keys(
+    value: &serde_json::Value,
+    path: &str,
+    flat: &mut serde_json::Map<String, serde_json::Value>,
+) {
+    use serde_json::Value;
+
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten_keys(value, &child_path(key), flat);
+            }
+        }
+        Value::Array(values) if !values.is_empty() => {
+            for (index, value) in values.iter().enumerate() {
+                flatten_keys(value, &child_path(&index.to_string()), flat);
+            }
+        }
+        leaf => {
+            flat.insert(path.to_string(), leaf.clone());
+        }
+    }
+}
+
+/// Collects the dotted paths of the numbers in `current` that changed by more than `threshold`
+/// percent relative to the same path in `previous`.
+fn find_spikes(
+    previous: &serde_json::Value,
+    current: &serde_json::Value,
+    path: &str,
+    threshold: f64,
+    spikes: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (previous, current) {
+        (Value::Number(previous), Value::Number(current)) => {
+            let (previous, current) = (previous.as_f64().unwrap(), current.as_f64().unwrap());
+            let spiked = if previous == 0.0 {
+                current != 0.0
+            } else {
+                ((current - previous) / previous).abs() * 100.0 > threshold
+            };
+            if spiked {
+                spikes.push(path.to_string());
+            }
+        }
+        (Value::Array(previous), Value::Array(current)) => {
+            for (index, (previous, current)) in previous.iter().zip(current).enumerate() {
+                find_spikes(
+                    previous,
+                    current,
+                    &child_path(&index.to_string()),
+                    threshold,
+                    spikes,
+                );
+            }
+        }
+        (Value::Object(previous), Value::Object(current)) => {
+            for (key, current) in current {
+                if let Some(previous) = previous.get(key) {
+                    find_spikes(previous, current, &child_path(key), threshold, spikes);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether two serialized samples differ, treating float fields within `epsilon` of each other
+/// as equal.
+fn differs(a: &serde_json::Value, b: &serde_json::Value, epsilon: f64) -> bool {
+    use serde_json::Value;
+
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) if a.is_f64() || b.is_f64() => {
+            (a.as_f64().unwrap() - b.as_f64().unwrap()).abs() > epsilon
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() != b.len() || a.iter().zip(b).any(|(a, b)| differs(a, b, epsilon))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() != b.len()
+                || a.iter()
+                    .any(|(key, a)| b.get(key).is_none_or(|b| differs(a, b, epsilon)))
+        }
+        (a, b) => a != b,
+    }
+}
+
+/// Reads an interval in seconds, as given to --interval, from a --interval-file.
+fn read_interval_file(path: &std::path::Path, align: bool) -> Result<Duration, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let content = content.trim();
+    match content.parse::<f32>().ok().and_then(duration_from_secs) {
+        Some(interval) if align && interval.is_zero() => {
+            Err("an interval of 0 can't be aligned to".to_string())
+        }
+        Some(interval) => Ok(interval),
+        None => Err(format!(
+            "{:?} is not a non-negative number of seconds, or too long",
+            content
+        )),
+    }
+}
+
+/// Parses a positive number of seconds, like `0.5` or `30`.
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.trim()
+        .parse::<f32>()
+        .ok()
+        .and_then(duration_from_secs)
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| format!("{:?} is not a positive number of seconds", text))
+}
+
+/// Writes a duration parsed by `parse_seconds` back as seconds.
+fn serialize_seconds<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs_f32())
+        .serialize(serializer)
+}
+
+/// `seconds` as a `Duration`, or `None` if it's negative, not a number or too long to compute a
+/// deadline from.
+fn duration_from_secs(seconds: f32) -> Option<Duration> {
+    let duration = Duration::try_from_secs_f32(seconds).ok()?;
+    Instant::now().checked_add(duration)?;
+    Some(duration)
+}
+
+/// Time left until the next multiple of `interval` past `epoch_base`, in Unix seconds.
+fn until_aligned(interval: Duration, epoch_base: f64) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let interval = interval.as_secs_f64();
+    let next = epoch_base + ((now - epoch_base) / interval).floor() * interval + interval;
+    Duration::from_secs_f64((next - now).max(0.0))
+}
+
+/// Sleeps for `duration`, pinging the systemd watchdog every `watchdog` in the meantime. The
+/// first ping is sent right away, so a refresh that never returns stops the pings.
+fn sleep_with_watchdog(duration: Duration, watchdog: Option<Duration>) {
+    let Some(watchdog) = watchdog else {
+        nap(duration);
+        return;
+    };
+    let start = Instant::now();
+    let deadline = start + duration;
+    let mut now = start;
+    while now < deadline && !interrupted() && !sample_requested() {
+        systemd_notify("WATCHDOG=1");
+        nap(watchdog.min(deadline - now));
+        now = Instant::now();
+    }
+}
+
+/// Sleeps for `duration`, waking up early for a caught signal.
+fn nap(duration: Duration) {
+    #[cfg(unix)]
+    if unix::catching_signals() {
+        let deadline = Instant::now() + duration;
+        while !interrupted() && !sample_requested() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(Duration::from_millis(100)));
+        }
+        return;
+    }
+    thread::sleep(duration);
+}
+
+/// Whether a caught SIGINT or SIGTERM asked asher to stop.
+fn interrupted() -> bool {
+    #[cfg(unix)]
+    return unix::interrupted();
+    #[cfg(not(unix))]
+    false
+}
+
+/// Whether a SIGUSR1 asked for a sample, with --sigusr1. Stays set until taken.
+fn sample_requested() -> bool {
+    #[cfg(unix)]
+    return unix::sample_requested();
+    #[cfg(not(unix))]
+    false
+}
+
+/// Whether a process with this PID exists. Without a way to tell, assumes it doesn't.
+fn process_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    return unix::process_running(pid);
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        false
+    }
+}
+
+fn take_sample_request() -> bool {
+    #[cfg(unix)]
+    return unix::take_sample_request();
+    #[cfg(not(unix))]
+    false
+}
+
+fn systemd_notify(state: &str) {
+    #[cfg(target_os = "linux")]
+    if let Err(err) = linux::sd_notify(state) {
+        warning!("failed to notify systemd of {}: {}", state, err);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, mac: &str, total: u64, current: u64) -> NetInterfaceStats {
+        NetInterfaceStats {
+            name: name.to_string(),
+            total_up: total,
+            total_down: total * 2,
+            up: current,
+            down: current * 2,
+            up_rate_bits: None,
+            down_rate_bits: None,
+            speed_mbps: None,
+            utilization_percent: None,
+            mac: mac.to_string(),
+        }
+    }
+
+    /// A bond over two members, a VLAN on one of them, loopback and a tunnel.
+    fn net() -> NetStats {
+        NetStats::from_interfaces(vec![
+            interface("eth0", "aa:bb:cc:00:00:01", 100, 10),
+            interface("eth1", "aa:bb:cc:00:00:01", 50, 5),
+            interface("bond0", "aa:bb:cc:00:00:01", 150, 15),
+            interface("eth0.10", "aa:bb:cc:00:00:02", 30, 3),
+            interface("lo", "", 1000, 1),
+            interface("tun0", "", 7, 0),
+        ])
+    }
+
+    fn names(net: &NetStats) -> Vec<&str> {
+        net.interfaces.iter().map(|net| net.name.as_str()).collect()
+    }
+
+    #[test]
+    fn select_interfaces_zero_keeps_totals() {
+        let mut net = net();
+        let names_selected = ["eth0".to_string(), "missing".to_string(), "lo".to_string()];
+        net.select_interfaces(&names_selected, MissingInterfaces::Zero);
+        assert_eq!(names(&net), ["eth0", "missing", "lo"]);
+        assert!(net.totals_match());
+        assert_eq!(net.total_up, 1100);
+    }
+
+    #[test]
+    fn select_interfaces_omit_keeps_totals() {
+        let mut net = net();
+        let names_selected = ["missing".to_string(), "tun0".to_string()];
+        net.select_interfaces(&names_selected, MissingInterfaces::Omit);
+        assert_eq!(names(&net), ["tun0"]);
+        assert!(net.totals_match());
+        assert_eq!(net.total_up, 7);
+    }
+
+    #[test]
+    fn dedup_interfaces_by_mac_keeps_totals() {
+        let mut net = net();
+        net.dedup_interfaces_by_mac();
+        assert_eq!(names(&net), ["bond0", "eth0.10", "lo", "tun0"]);
+        assert!(net.totals_match());
+    }
+
+    #[test]
+    fn cap_interfaces_keeps_totals() {
+        let mut net = net();
+        net.cap_interfaces(2);
+        assert_eq!(names(&net), ["bond0", "eth0", "other"]);
+        assert!(net.totals_match());
+        assert_eq!(net.total_up, 1337);
+    }
+
+    fn args(flags: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("asher").chain(flags.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn missing_subsystem_is_a_collection_error() {
+        let stats: SysStats = serde_json::from_str("{}").unwrap();
+        let strict = args(&["--only", "net", "--fail-on-missing-subsystem"]);
+        assert_eq!(
+            require_subsystems(&stats, &strict),
+            Err(ExitCode::Collection)
+        );
+        assert_eq!(
+            require_subsystems(&stats, &args(&["--only", "net"])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn sort_interfaces_keeps_totals() {
+        let mut net = net();
+        net.cap_interfaces(3);
+        net.sort_interfaces();
+        assert_eq!(names(&net), ["bond0", "eth0", "eth1", "other"]);
+        assert!(net.totals_match());
+    }
+
+    #[test]
+    fn cpu_refresh_waits_for_the_minimum_interval() {
+        let minimum = System::MINIMUM_CPU_UPDATE_INTERVAL;
+        assert!(!cpu_refresh_due(Duration::ZERO));
+        assert!(!cpu_refresh_due(minimum - Duration::from_nanos(1)));
+        assert!(cpu_refresh_due(minimum));
+        assert!(cpu_refresh_due(minimum + Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn startup_warmup_lets_refresh_measure_cpu() {
+        let args = args(&["--only", "cpu", "--startup-warmup-ms", "1"]);
+        let mut system = lock(SYSTEM.get_or_init(|| new_system(&args)));
+        let last_cpu_refresh = || *lock(LAST_CPU_REFRESH.get().unwrap());
+        let initial = last_cpu_refresh();
+        startup_warmup(&args);
+        refresh(&mut system, &args);
+        assert!(last_cpu_refresh() > initial);
+    }
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("1GB"), Ok(1_000_000_000));
+        assert_eq!(parse_size("1.5 GiB"), Ok(3 << 29));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("GB").is_err());
+        assert!(parse_size("12 parsecs").is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_past_u64() {
+        assert!(parse_size("18000000tb").is_ok());
+        assert!(parse_size("19000000tb").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn rounds_to_significant_figures() {
+        assert_eq!(round_to_sigfigs(0, 3), 0);
+        assert_eq!(round_to_sigfigs(16_234_567, 3), 16_200_000);
+        assert_eq!(round_to_sigfigs(1234, 4), 1234);
+        assert_eq!(round_to_sigfigs(1234, 6), 1234);
+        assert_eq!(round_to_sigfigs(999_950, 4), 1_000_000);
+    }
 
-        println!("{}", serde_json::to_string(&stats).unwrap());
+    #[test]
+    fn keeps_values_whose_rounding_overflows() {
+        assert_eq!(round_to_sigfigs(u64::MAX, 2), 18_000_000_000_000_000_000);
+        assert_eq!(round_to_sigfigs(u64::MAX, 4), u64::MAX);
     }
 }
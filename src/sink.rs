@@ -0,0 +1,140 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+use crate::Format;
+
+/// Where a sink's lines go. A new kind of destination only needs a variant here and its arm in
+/// [`Sink::write`].
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Stdout,
+    Stderr,
+    /// Appended to, and reopened for every write so rotated files are picked up.
+    File(PathBuf),
+}
+
+/// An output for --sink, written as `TARGET[:FORMAT]` where the target is `stdout`, `stderr` or
+/// a file path. A path may contain colons; only a suffix naming a format is split off.
+#[derive(Debug, Clone)]
+pub struct Sink {
+    source: String,
+    target: Target,
+    format: Format,
+}
+
+impl Sink {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let (target, format) = source
+            .rsplit_once(':')
+            .and_then(|(target, format)| Some((target, Format::from_str(format, true).ok()?)))
+            .unwrap_or((source, Format::Auto));
+        let target = match target {
+            "" => return Err("missing target".to_string()),
+            "stdout" | "-" => Target::Stdout,
+            "stderr" => Target::Stderr,
+            path => Target::File(PathBuf::from(path)),
+        };
+        Ok(Self {
+            source: source.to_string(),
+            target,
+            format,
+        })
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        match &self.target {
+            Target::Stdout => io::stdout().is_terminal(),
+            Target::Stderr => io::stderr().is_terminal(),
+            Target::File(_) => false,
+        }
+    }
+
+    /// The format samples are written in, with `auto` resolved like --format.
+    pub fn format(&self) -> Format {
+        match self.format {
+            Format::Auto if self.is_terminal() => Format::Table,
+            Format::Auto => Format::Json,
+            format => format,
+        }
+    }
+
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        match &self.target {
+            Target::Stdout => writeln!(io::stdout(), "{}", text),
+            Target::Stderr => writeln!(io::stderr(), "{}", text),
+            Target::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", text)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Serialize for Sink {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> (Target, Format) {
+        let sink = Sink::parse(source).unwrap();
+        (sink.target, sink.format)
+    }
+
+    fn file(path: &str) -> Target {
+        Target::File(PathBuf::from(path))
+    }
+
+    #[test]
+    fn parses_standard_streams() {
+        assert_eq!(parse("stdout"), (Target::Stdout, Format::Auto));
+        assert_eq!(parse("-"), (Target::Stdout, Format::Auto));
+        assert_eq!(parse("stderr:table"), (Target::Stderr, Format::Table));
+    }
+
+    #[test]
+    fn parses_files() {
+        assert_eq!(
+            parse("samples.ndjson"),
+            (file("samples.ndjson"), Format::Auto)
+        );
+        assert_eq!(
+            parse("samples.ndjson:json"),
+            (file("samples.ndjson"), Format::Json)
+        );
+    }
+
+    #[test]
+    fn keeps_colons_that_start_no_format() {
+        assert_eq!(
+            parse("/tmp/run:1/x.ndjson"),
+            (file("/tmp/run:1/x.ndjson"), Format::Auto)
+        );
+        assert_eq!(
+            parse("/tmp/run:1/x.ndjson:table"),
+            (file("/tmp/run:1/x.ndjson"), Format::Table)
+        );
+    }
+
+    #[test]
+    fn rejects_a_missing_target() {
+        assert!(Sink::parse(":json").is_err());
+    }
+}
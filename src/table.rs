@@ -0,0 +1,510 @@
+use crate::{
+    Args, CheckResult, DiskUsage, MemoryUnit, NetInterfaceInfo, SelftestResult, SensorStats,
+    SysStats, TopProcess, UnitSystem,
+};
+
+/// A value that can be listed as one row of a table.
+pub trait TableRow {
+    fn header() -> Vec<&'static str>;
+    fn row(&self) -> Vec<String>;
+}
+
+impl TableRow for SensorStats {
+    fn header() -> Vec<&'static str> {
+        vec!["LABEL", "TEMP", "MAX", "CRITICAL"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.label.clone(),
+            format!("{:.1}", self.temperature),
+            format!("{:.1}", self.max),
+            self.critical
+                .map_or("-".to_string(), |critical| format!("{:.1}", critical)),
+        ]
+    }
+}
+
+impl TableRow for NetInterfaceInfo {
+    fn header() -> Vec<&'static str> {
+        vec!["INTERFACE", "MAC", "TOTAL UP", "TOTAL DOWN"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.mac.clone(),
+            human_bytes(self.total_up),
+            human_bytes(self.total_down),
+        ]
+    }
+}
+
+impl TableRow for DiskUsage {
+    fn header() -> Vec<&'static str> {
+        vec!["PATH", "MOUNT", "FS", "USED", "FREE", "TOTAL", "USE%"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.path.clone(),
+            self.mount_point.clone(),
+            self.fs_type.clone(),
+            size_in(self.used, self.unit),
+            size_in(self.free, self.unit),
+            size_in(self.total, self.unit),
+            format!("{:.1}%", self.used_percent),
+        ]
+    }
+}
+
+impl TableRow for TopProcess {
+    fn header() -> Vec<&'static str> {
+        vec!["PID", "NAME", "CPU%", "MEM"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.pid.to_string(),
+            self.name.clone(),
+            format!("{:.1}%", self.cpu_usage),
+            human_bytes(self.memory),
+        ]
+    }
+}
+
+impl TableRow for CheckResult {
+    fn header() -> Vec<&'static str> {
+        vec![
+            "METRIC",
+            "STATUS",
+            "USAGE",
+            "BASELINE",
+            "GROWTH",
+            "MAX GROWTH",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.metric.to_string(),
+            if self.passed { "ok" } else { "FAIL" }.to_string(),
+            format!("{:.1}%", self.usage),
+            format!("{:.1}%", self.baseline),
+            format!("{:+.1}", self.growth),
+            format!("{:.1}", self.max_growth),
+        ]
+    }
+}
+
+impl TableRow for SelftestResult {
+    fn header() -> Vec<&'static str> {
+        vec!["FORM", "STATUS", "PROBLEMS"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.form.to_string(),
+            if self.passed { "ok" } else { "FAIL" }.to_string(),
+            if self.problems.is_empty() {
+                "-".to_string()
+            } else {
+                self.problems.join(", ")
+            },
+        ]
+    }
+}
+
+pub fn render_rows<T: TableRow>(rows: &[T]) -> String {
+    let mut lines = vec![T::header().into_iter().map(str::to_string).collect()];
+    lines.extend(rows.iter().map(TableRow::row));
+    align(&lines, "").join("\n")
+}
+
+/// Renders a sample as a block of lines, with a section per subsystem, followed by an empty line.
+/// `is_terminal` is whether the destination is one, for --color auto.
+pub fn render_stats(stats: &SysStats, args: &Args, is_terminal: bool) -> String {
+    let raw_size = |value: u64| match args.memory_unit {
+        MemoryUnit::Bytes if !args.legacy_kib_memory => human_bytes(value),
+        MemoryUnit::Bytes => format!("{} KiB", value),
+        unit => format!("{} {}", value, unit.suffix()),
+    };
+    // With --align-output-width, numbers are padded to the widest they can plausibly get, so
+    // columns stay put from one sample to the next: sizes to that of their total, or of
+    // `1023.9 KiB` when the unit is picked per value, and percentages to that of `100.0%`.
+    let fixed = args.align_output_width;
+    let size_width = |total: String| {
+        if fixed {
+            SIZE_WIDTH.max(total.len())
+        } else {
+            0
+        }
+    };
+    let percent_width = if fixed { PERCENT_WIDTH } else { 0 };
+    let mem_width = size_width(
+        stats
+            .mem
+            .as_ref()
+            .map_or(String::new(), |mem| raw_size(mem.total)),
+    );
+    let disk_width = size_width(stats.disks.as_ref().map_or(String::new(), |disks| {
+        size_in(disks.total, args.memory_unit)
+    }));
+    let size = |value: u64| pad(raw_size(value), mem_width);
+    let disk_size = |value: u64| pad(size_in(value, args.memory_unit), disk_width);
+    let bytes = |value: u64| pad(human_bytes(value), size_width(String::new()));
+    let percent = |value: f32| pad(format!("{:.1}%", value), percent_width);
+    let palette = Palette::new(args, is_terminal);
+    let colored = |value: f32| pad(palette.percent(value), percent_width);
+    let colored_of = |part: u64, total: u64| pad(palette.percent_of(part, total), percent_width);
+    let mut out = Vec::new();
+
+    if let Some(mem) = &stats.mem {
+        let mut line = format!(
+            "MEM    used {} / {} ({})   available {}",
+            size(mem.used),
+            size(mem.total),
+            colored_of(mem.used, mem.total),
+            size(mem.available),
+        );
+        if let (Some(used_swap), Some(total_swap)) = (mem.used_swap, mem.total_swap) {
+            line.push_str(&format!(
+                "   swap {} / {}",
+                size(used_swap),
+                size(total_swap)
+            ));
+        }
+        if let (Some(cached), Some(buffers), Some(shared)) = (mem.cached, mem.buffers, mem.shared) {
+            line.push_str(&format!(
+                "   cached {}   buffers {}   shared {}",
+                size(cached),
+                size(buffers),
+                size(shared)
+            ));
+        }
+        out.push(line);
+    }
+    if let Some(cpu) = &stats.cpu {
+        let cores: Vec<String> = cpu.cpus.iter().map(|core| colored(core.usage)).collect();
+        let mut line = format!("CPU    usage {}", colored(cpu.usage));
+        if let Some(process_sum) = cpu.process_sum {
+            line.push_str(&format!("   processes {}", percent(process_sum)));
+        }
+        if let Some(allowed) = cpu.allowed_cpus.filter(|&allowed| allowed < cpu.cpus.len()) {
+            line.push_str(&format!(
+                "   allowed {} of {} cpus",
+                allowed,
+                cpu.cpus.len()
+            ));
+        }
+        if let Some(events) = cpu.throttle_events {
+            line.push_str(&format!("   throttled {}x", events));
+        }
+        line.push_str(&format!("   cores {}", cores.join(" ")));
+        out.push(line);
+        if let Some(sparklines) = &cpu.cpus_sparkline {
+            out.push(format!("       history {}", sparklines.join(" ")));
+        }
+        if cpu.packages.len() > 1 {
+            let packages: Vec<String> = cpu
+                .packages
+                .iter()
+                .map(|package| format!("{}:{}", package.id, percent(package.usage)))
+                .collect();
+            out.push(format!("       packages {}", packages.join(" ")));
+        }
+        if let Some(times) = &cpu.times {
+            let mut line = format!(
+                "       user {}   nice {}   system {}   idle {}",
+                percent(times.user),
+                percent(times.nice),
+                percent(times.system),
+                percent(times.idle)
+            );
+            for (name, value) in [
+                ("iowait", times.iowait),
+                ("irq", times.irq),
+                ("softirq", times.softirq),
+            ] {
+                if let Some(value) = value {
+                    line.push_str(&format!("   {} {}", name, percent(value)));
+                }
+            }
+            if let Some(steal) = cpu.steal_percent {
+                line.push_str(&format!("   steal {}", percent(steal)));
+            }
+            out.push(line);
+        }
+    }
+    if let Some(processes) = &stats.processes {
+        let mut line = format!("PROCS  {} processes", processes.count);
+        if let Some(threads) = processes.total_threads {
+            line.push_str(&format!(", {} threads", threads));
+        }
+        out.push(line);
+    }
+    if let Some(sessions) = &stats.sessions {
+        let mut line = format!(
+            "USERS  {} users, {} sessions",
+            sessions.users, sessions.sessions
+        );
+        if let Some(usernames) = &sessions.usernames {
+            line.push_str(&format!("   {}", usernames.join(" ")));
+        }
+        out.push(line);
+    }
+    if let Some(own) = &stats.own {
+        out.push(format!(
+            "SELF   pid {}   cpu {}   memory {}",
+            own.pid,
+            percent(own.cpu_usage),
+            size(own.memory)
+        ));
+    }
+    if let Some(host) = &stats.host {
+        out.push(format!("HOST   entropy {} bits", host.entropy_avail));
+    }
+    if let Some(top) = &stats.top_processes {
+        out.push("TOP".to_string());
+        let mut header = vec!["PID", "NAME", "CPU", "MEMORY"];
+        if args.faults {
+            header.extend(["MINFLT", "MAJFLT"]);
+        }
+        let mut rows = vec![header.into_iter().map(str::to_string).collect()];
+        rows.extend(top.iter().map(|process| {
+            let mut row = vec![
+                process.pid.to_string(),
+                process.name.clone(),
+                percent(process.cpu_usage),
+                size(process.memory),
+            ];
+            if args.faults {
+                let count = |count: Option<u64>| count.map_or("-".to_string(), |c| c.to_string());
+                row.push(count(process.minor_faults));
+                row.push(count(process.major_faults));
+            }
+            row
+        }));
+        out.extend(align(&rows, "       "));
+    }
+    if let Some(disks) = &stats.disks {
+        let mut line = format!(
+            "DISK   used {} / {} ({})   read {}/s   write {}/s",
+            disk_size(disks.used),
+            disk_size(disks.total),
+            colored_of(disks.used, disks.total),
+            disk_size(disks.read_rate),
+            disk_size(disks.write_rate),
+        );
+        if let Some(busiest) = &disks.busiest_disk_io {
+            line.push_str(&format!("   busiest {}", busiest));
+        }
+        out.push(line);
+        let mut rows = vec![["MOUNT", "FS", "USED", "TOTAL", "USE%", "READ", "WRITE"]
+            .map(str::to_string)
+            .to_vec()];
+        let io = |value: Option<u64>| value.map_or("-".to_string(), disk_size);
+        rows.extend(disks.disks.iter().map(|disk| {
+            vec![
+                disk.mount_point.clone(),
+                disk.fs_type.clone(),
+                disk_size(disk.used),
+                disk_size(disk.total),
+                colored_of(disk.used, disk.total),
+                io(disk.read),
+                io(disk.write),
+            ]
+        }));
+        out.extend(align(&rows, "       "));
+    }
+    if let Some(net) = &stats.net {
+        let mut line = format!(
+            "NET    up {}   down {}   total up {}   total down {}",
+            bytes(net.up),
+            bytes(net.down),
+            bytes(net.total_up),
+            bytes(net.total_down),
+        );
+        if let (Some(up), Some(down)) = (net.up_rate_bits, net.down_rate_bits) {
+            line.push_str(&format!("   rate up {} bit/s   down {} bit/s", up, down));
+        }
+        out.push(line);
+        let mut header = vec!["INTERFACE", "UP", "DOWN", "TOTAL UP", "TOTAL DOWN"];
+        if args.net_speed {
+            header.extend(["SPEED", "UTIL%"]);
+        }
+        let mut rows = vec![header.into_iter().map(str::to_string).collect()];
+        rows.extend(net.interfaces.iter().map(|interface| {
+            let mut row = vec![
+                interface.name.clone(),
+                bytes(interface.up),
+                bytes(interface.down),
+                bytes(interface.total_up),
+                bytes(interface.total_down),
+            ];
+            if args.net_speed {
+                row.push(
+                    interface
+                        .speed_mbps
+                        .flatten()
+                        .map_or("-".to_string(), |speed| format!("{} Mbit/s", speed)),
+                );
+                row.push(
+                    interface
+                        .utilization_percent
+                        .flatten()
+                        .map_or("-".to_string(), &colored),
+                );
+            }
+            row
+        }));
+        out.extend(align(&rows, "       "));
+    }
+    for (key, err) in &stats.errors {
+        out.push(format!(
+            "ERROR  {}: {}",
+            key.trim_end_matches("_error"),
+            err
+        ));
+    }
+    let mut timing = Vec::new();
+    if let Some(interval) = stats.sample_interval {
+        timing.push(format!("interval {:.3}s", interval));
+    }
+    if let Some(duration) = stats.collect_duration_ms {
+        timing.push(format!("collection {:.1} ms", duration));
+    }
+    if !timing.is_empty() {
+        out.push(format!("TIME   {}", timing.join("   ")));
+    }
+    if let Some(spikes) = &stats.spikes {
+        if !spikes.is_empty() {
+            out.push(format!("SPIKES {}", spikes.join(" ")));
+        }
+    }
+    // Keep consecutive samples apart in loop mode.
+    out.push(String::new());
+    out.join("\n")
+}
+
+/// Colors usage percentages by --color-thresholds, when --color is on.
+struct Palette {
+    enabled: bool,
+    warn: f32,
+    crit: f32,
+}
+
+impl Palette {
+    fn new(args: &Args, is_terminal: bool) -> Self {
+        Self {
+            enabled: args.color.enabled(is_terminal),
+            warn: args.color_thresholds[0],
+            crit: args.color_thresholds[1],
+        }
+    }
+
+    fn percent(&self, value: f32) -> String {
+        let text = format!("{:.1}%", value);
+        if !self.enabled {
+            return text;
+        }
+        let color = if value >= self.crit {
+            RED
+        } else if value >= self.warn {
+            YELLOW
+        } else {
+            GREEN
+        };
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    }
+
+    fn percent_of(&self, part: u64, total: u64) -> String {
+        if total == 0 {
+            return "-".to_string();
+        }
+        self.percent((part as f64 * 100.0 / total as f64) as f32)
+    }
+}
+
+const RED: u8 = 31;
+const GREEN: u8 = 32;
+const YELLOW: u8 = 33;
+
+const SIZE_WIDTH: usize = "1023.9 KiB".len();
+const PERCENT_WIDTH: usize = "100.0%".len();
+
+/// Right-aligns `text` to `width` characters on screen.
+fn pad(text: String, width: usize) -> String {
+    let visible = visible_width(&text);
+    if visible >= width {
+        return text;
+    }
+    format!("{}{}", " ".repeat(width - visible), text)
+}
+
+/// Number of characters `cell` takes up on screen, leaving out color escapes.
+fn visible_width(cell: &str) -> usize {
+    let mut width = 0;
+    let mut chars = cell.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Pads every column to its widest cell.
+fn align(rows: &[Vec<String>], indent: &str) -> Vec<String> {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| visible_width(cell))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| {
+                    format!("{}{}", cell, " ".repeat(width - visible_width(cell)))
+                })
+                .collect();
+            format!("{}{}", indent, cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+/// Formats a size that is already in `unit`, using the most readable unit for plain bytes.
+fn size_in(value: u64, unit: MemoryUnit) -> String {
+    match unit {
+        MemoryUnit::Bytes => human_bytes(value),
+        unit => format!("{} {}", value, unit.suffix()),
+    }
+}
+
+/// Formats a byte count in the largest unit of --unit-system it reaches.
+pub fn human_bytes(bytes: u64) -> String {
+    let (base, units) = match UnitSystem::current() {
+        UnitSystem::Binary => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB"]),
+        UnitSystem::Si => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB"]),
+    };
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Field(String),
+}
+
+/// A line format for --output-template, with `{dotted.path}` placeholders for sample fields and
+/// `{{`/`}}` for literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err("unclosed placeholder".to_string()),
+                            Some(c) => field.push(c),
+                        }
+                    }
+                    let field = field.trim();
+                    if field.is_empty() {
+                        return Err("empty placeholder".to_string());
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Field(field.to_string()));
+                }
+                '}' => return Err("unmatched '}', write '}}' for a literal one".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Field(field) => Some(field.as_str()),
+            Segment::Text(_) => None,
+        })
+    }
+
+    /// Fills in the placeholders from a sample flattened by dotted path. Fields the sample
+    /// doesn't have, such as optional ones or indices past the end of a list, render as `-`.
+    pub fn render(&self, sample: &Map<String, Value>) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Field(field) => match sample.get(field) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(Value::Null) | None => "-".to_string(),
+                    Some(value) => value.to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, sample: Value) -> String {
+        let Value::Object(sample) = sample else {
+            panic!("sample isn't an object");
+        };
+        Template::parse(source).unwrap().render(&sample)
+    }
+
+    #[test]
+    fn substitutes_fields_by_path() {
+        let sample = serde_json::json!({"cpu.usage": 12.5, "host.name": "box"});
+        assert_eq!(
+            render("{host.name}: cpu {cpu.usage}%", sample),
+            "box: cpu 12.5%"
+        );
+    }
+
+    #[test]
+    fn renders_missing_fields_as_a_dash() {
+        let sample = serde_json::json!({"mem.cached": null});
+        assert_eq!(render("{mem.cached} {cpu.usage}", sample), "- -");
+    }
+
+    #[test]
+    fn unescapes_doubled_braces() {
+        let sample = serde_json::json!({"cpu.usage": 3});
+        assert_eq!(render("{{{cpu.usage}}}", sample), "{3}");
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(Template::parse("{cpu.usage").is_err());
+        assert!(Template::parse("{cpu{usage}").is_err());
+        assert!(Template::parse("cpu}").is_err());
+        assert!(Template::parse("{ }").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    ffi::CString,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static CATCHING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static SAMPLE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_sample_request(_signal: libc::c_int) {
+    SAMPLE_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGINT and SIGTERM set a flag, see [`interrupted`], instead of terminating asher, so it
+/// can finish what it's writing first.
+pub fn catch_interrupts() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+    CATCHING.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGUSR1 set a flag, see [`take_sample_request`], instead of terminating asher.
+pub fn catch_sample_requests() {
+    let handler = on_sample_request as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: as in catch_interrupts.
+    unsafe {
+        libc::signal(libc::SIGUSR1, handler);
+    }
+    CATCHING.store(true, Ordering::SeqCst);
+}
+
+/// Whether any signal is caught, so sleeps have to check for it.
+pub fn catching_signals() -> bool {
+    CATCHING.load(Ordering::SeqCst)
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn sample_requested() -> bool {
+    SAMPLE_REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn take_sample_request() -> bool {
+    SAMPLE_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Whether a process with this PID exists, even if it belongs to another user.
+pub fn process_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the signal could be sent.
+    let sent = unsafe { libc::kill(pid, 0) } == 0;
+    sent || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Total and free inodes of the filesystem mounted at `mount_point`.
+pub fn inode_counts(mount_point: &str) -> Option<(u64, u64)> {
+    let path = CString::new(mount_point).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read once statvfs has filled it in.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Some((stat.f_files as u64, stat.f_ffree as u64))
+}
@@ -0,0 +1,77 @@
+//! The exit statuses documented on `ExitCode`, checked against the built binary.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn asher(args: &[&str]) -> i32 {
+    asher_with_input(args, "")
+}
+
+fn asher_with_input(args: &[&str], input: &str) -> i32 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_asher"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait().unwrap().code().unwrap()
+}
+
+#[test]
+fn clap_errors_are_usage_errors() {
+    assert_eq!(asher(&["--no-such-flag"]), 3);
+    assert_eq!(asher(&["--memory-unit", "furlongs"]), 3);
+}
+
+#[test]
+fn help_is_not_an_error() {
+    assert_eq!(asher(&["--help"]), 0);
+}
+
+#[test]
+fn validate_errors_are_usage_errors() {
+    assert_eq!(asher(&["--byte-sigfigs", "0"]), 3);
+    assert_eq!(asher(&["--fail-on-missing-subsystem"]), 3);
+    assert_eq!(asher(&["check", "--compare-to", "baseline.json"]), 3);
+}
+
+#[test]
+fn unreadable_replay_input_is_a_usage_error() {
+    assert_eq!(
+        asher(&["replay", "--input", "/nonexistent/samples.ndjson"]),
+        3
+    );
+}
+
+#[test]
+fn replay_of_a_sample_succeeds() {
+    assert_eq!(asher_with_input(&["replay", "--input", "-"], "{}\n"), 0);
+}
+
+#[test]
+fn strict_json_failure_is_a_collection_error() {
+    // Too large for the f32 field, so it reads back as infinity.
+    let sample = r#"{"self":{"pid":1,"cpu_usage":1e300,"memory":0}}"#;
+    assert_eq!(
+        asher_with_input(&["--strict-json", "replay", "--input", "-"], sample),
+        4
+    );
+    assert_eq!(asher_with_input(&["replay", "--input", "-"], sample), 0);
+}
+
+#[test]
+fn wait_for_timeout_is_critical() {
+    assert_eq!(
+        asher(&["wait-for", "--name", "no-such-process-", "--timeout", "0"]),
+        2
+    );
+}